@@ -1,7 +1,16 @@
 use bencher::{benchmark_group, benchmark_main, Bencher};
 use tmpkey::{AnySlab, Slab};
 
-benchmark_group!(primary, insert, insert_and_get, insert_and_remove);
+benchmark_group!(
+    primary,
+    insert,
+    insert_and_get,
+    insert_and_remove,
+    get_warm,
+    get_unchecked_warm,
+    get2_mut_link,
+    get_mut_pair_link
+);
 benchmark_group!(any_slab, any_insert);
 benchmark_group!(
     comparison,
@@ -41,6 +50,46 @@ fn insert_and_remove(b: &mut Bencher) {
     });
 }
 
+fn get_warm(b: &mut Bencher) {
+    let mut map = Slab::default();
+    let keys: Vec<_> = (0..1000).map(|i| map.insert(i)).collect();
+    b.iter(|| keys.iter().map(|&k| *map.get(k).unwrap()).sum::<u64>());
+}
+
+fn get_unchecked_warm(b: &mut Bencher) {
+    let mut map = Slab::default();
+    let keys: Vec<_> = (0..1000).map(|i| map.insert(i)).collect();
+    b.iter(|| {
+        keys.iter()
+            .map(|&k| unsafe { *map.get_unchecked(k) })
+            .sum::<u64>()
+    });
+}
+
+fn get2_mut_link(b: &mut Bencher) {
+    let mut map = Slab::default();
+    let keys: Vec<_> = (0..1000u64).map(|i| map.insert(i)).collect();
+    b.iter(|| {
+        for pair in keys.windows(2) {
+            let (x, y) = map.get2_mut(pair[0], pair[1]).unwrap();
+            *y = y.wrapping_add(*x);
+        }
+    });
+}
+
+fn get_mut_pair_link(b: &mut Bencher) {
+    let map = core::cell::RefCell::new(Slab::default());
+    let keys: Vec<_> = (0..1000u64).map(|i| map.borrow_mut().insert(i)).collect();
+    b.iter(|| {
+        for pair in keys.windows(2) {
+            let x = *map.borrow_mut().get_mut(pair[0]).unwrap();
+            let mut map = map.borrow_mut();
+            let y = map.get_mut(pair[1]).unwrap();
+            *y = y.wrapping_add(x);
+        }
+    });
+}
+
 fn slotmap_insert(b: &mut Bencher) {
     let mut map = slotmap::SlotMap::new();
     b.iter(|| map.insert(5));
@@ -1,59 +1,325 @@
-use std::{any::Any, fmt::Debug, iter::FilterMap, num::NonZeroU32, ops::Not};
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
-pub struct KeyData<T> {
-    index: u32,
-    version: NonZeroU32,
-    __phantom: core::marker::PhantomData<T>,
+extern crate alloc;
+
+use alloc::{boxed::Box, format, vec, vec::Vec};
+use core::{
+    any::{Any, TypeId},
+    fmt::{Debug, Display},
+    hash::Hash,
+    iter::{Enumerate, FusedIterator},
+    num::{NonZeroU32, NonZeroU64},
+    ops::{ControlFlow, Index, IndexMut},
+};
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+}
+
+/// Integer type used for slot indices and versions. Slabs, keys and
+/// secondary maps default to `u32`; `u64` lifts the 4 billion slot limit at
+/// the cost of wider keys, see [`WideKey`].
+pub trait SlotInt:
+    Copy + Ord + Hash + Debug + Display + Send + Sync + 'static + private::Sealed
+{
+    /// Non-zero counterpart used for versions.
+    type Version: Copy + Ord + Hash + Debug + Display + Send + Sync + 'static;
+
+    const MAX: Self;
+    /// Version of a slot that has never been occupied.
+    const FIRST_VERSION: Self::Version;
+    /// Version of the first value stored in a fresh slot.
+    const FRESH_VERSION: Self::Version;
+
+    /// Converts an index, which must fit; checked in debug builds.
+    fn from_usize(n: usize) -> Self;
+    /// Converts an index, returning `None` if it doesn't fit.
+    fn try_from_usize(n: usize) -> Option<Self>;
+    fn to_usize(self) -> usize;
+    fn get(version: Self::Version) -> Self;
+    fn next_version(version: Self::Version) -> Option<Self::Version>;
+    fn prev_version(version: Self::Version) -> Option<Self::Version>;
+}
+
+impl SlotInt for u32 {
+    type Version = NonZeroU32;
+
+    const MAX: Self = u32::MAX;
+    const FIRST_VERSION: NonZeroU32 = NonZeroU32::new(1).unwrap();
+    const FRESH_VERSION: NonZeroU32 = NonZeroU32::new(2).unwrap();
+
+    fn from_usize(n: usize) -> Self {
+        debug_assert!(u32::try_from(n).is_ok(), "index {n} does not fit in u32");
+        n as u32
+    }
+
+    fn try_from_usize(n: usize) -> Option<Self> {
+        u32::try_from(n).ok()
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    fn get(version: NonZeroU32) -> Self {
+        version.get()
+    }
+
+    fn next_version(version: NonZeroU32) -> Option<NonZeroU32> {
+        version.checked_add(1)
+    }
+
+    fn prev_version(version: NonZeroU32) -> Option<NonZeroU32> {
+        NonZeroU32::new(version.get() - 1)
+    }
+}
+
+impl SlotInt for u64 {
+    type Version = NonZeroU64;
+
+    const MAX: Self = u64::MAX;
+    const FIRST_VERSION: NonZeroU64 = NonZeroU64::new(1).unwrap();
+    const FRESH_VERSION: NonZeroU64 = NonZeroU64::new(2).unwrap();
+
+    fn from_usize(n: usize) -> Self {
+        debug_assert!(u64::try_from(n).is_ok(), "index {n} does not fit in u64");
+        n as u64
+    }
+
+    fn try_from_usize(n: usize) -> Option<Self> {
+        u64::try_from(n).ok()
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    fn get(version: NonZeroU64) -> Self {
+        version.get()
+    }
+
+    fn next_version(version: NonZeroU64) -> Option<NonZeroU64> {
+        version.checked_add(1)
+    }
+
+    fn prev_version(version: NonZeroU64) -> Option<NonZeroU64> {
+        NonZeroU64::new(version.get() - 1)
+    }
+}
+
+/// Keys don't own a `T`, so they are `Send` and `Sync` whatever `T` is.
+pub struct KeyData<T, Idx: SlotInt = u32> {
+    index: Idx,
+    version: Idx::Version,
+    __phantom: core::marker::PhantomData<fn() -> T>,
 }
 
 #[macro_export]
 macro_rules! key {
     ($v:vis $name:ident) => {
-        #[derive(Clone, Copy, Debug)]
+        $crate::new_key_type! {
+            $v struct $name;
+        }
+    };
+}
+
+key!(pub DefaultKey);
+
+/// Defines key types that can't be mixed up with each other or with
+/// [`DefaultKey`]. Each generated `Name<T>` wraps a [`KeyData`] and
+/// implements [`Key`], along with `Clone`, `Copy`, `Debug`, `PartialEq`,
+/// `Eq`, `Hash` and `Ord`. Keys use `u32` indices unless another
+/// [`SlotInt`] is given, as in `pub struct BigKey: u64;`.
+///
+/// ```
+/// use tmpkey::{new_key_type, Slab};
+///
+/// new_key_type! {
+///     pub struct EntityKey;
+///     pub struct ItemKey;
+/// }
+///
+/// let mut entities: Slab<EntityKey<&str>, &str> = Slab::new();
+/// let player = entities.insert("player");
+/// assert_eq!(entities.get(player), Some(&"player"));
+/// ```
+///
+/// A key from one slab is rejected by a slab with another key type:
+///
+/// ```compile_fail
+/// use tmpkey::{new_key_type, Slab};
+///
+/// new_key_type! {
+///     pub struct EntityKey;
+///     pub struct ItemKey;
+/// }
+///
+/// let mut entities: Slab<EntityKey<&str>, &str> = Slab::new();
+/// let items: Slab<ItemKey<&str>, &str> = Slab::new();
+/// let player = entities.insert("player");
+/// items.get(player);
+/// ```
+#[macro_export]
+macro_rules! new_key_type {
+    ($(#[$meta:meta])* $v:vis struct $name:ident; $($rest:tt)*) => {
+        $crate::new_key_type!($(#[$meta])* $v struct $name: u32; $($rest)*);
+    };
+    ($(#[$meta:meta])* $v:vis struct $name:ident: $int:ty; $($rest:tt)*) => {
+        $(#[$meta])*
         $v struct $name<T> {
-            data: KeyData<T>,
+            data: $crate::KeyData<T, $int>,
         }
 
-        impl<T> Key<T> for $name<T> {
-            fn data(&self) -> &KeyData<T> {
+        impl<T> Clone for $name<T> {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+
+        impl<T> Copy for $name<T> {}
+
+        impl<T> core::fmt::Debug for $name<T> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("data", &self.data)
+                    .finish()
+            }
+        }
+
+        impl<T> PartialEq for $name<T> {
+            fn eq(&self, other: &Self) -> bool {
+                self.data == other.data
+            }
+        }
+
+        impl<T> Eq for $name<T> {}
+
+        impl<T> PartialOrd for $name<T> {
+            fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl<T> Ord for $name<T> {
+            fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+                self.data.cmp(&other.data)
+            }
+        }
+
+        impl<T> core::hash::Hash for $name<T> {
+            fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+                core::hash::Hash::hash(&self.data, state);
+            }
+        }
+
+        impl<T> $crate::Key<T, $int> for $name<T> {
+            fn data(&self) -> &$crate::KeyData<T, $int> {
                 &self.data
             }
 
-            fn init(version: NonZeroU32, index: u32) -> Self {
+            fn init(version: <$int as $crate::SlotInt>::Version, index: $int) -> Self {
                 Self {
-                    data: KeyData {
-                        index,
-                        version,
-						__phantom: core::marker::PhantomData,
-                    },
+                    data: $crate::KeyData::new(version, index),
                 }
             }
         }
+
+        $crate::new_key_type!($($rest)*);
     };
+    () => {};
 }
 
-key!(pub DefaultKey);
+new_key_type! {
+    /// A key with `u64` indices and versions, for slabs that outgrow `u32`.
+    pub struct WideKey: u64;
+
+    /// A key returned by [`AnySlab::insert`], typed by the inserted value.
+    pub struct TypedKey;
+}
+
+/// A slab keyed by [`WideKey`].
+pub type WideSlab<V> = Slab<WideKey<V>, V, u64>;
+
+/// A slab's internal state, see [`Slab::into_raw_parts`].
+pub struct RawParts<V, Idx: SlotInt = u32> {
+    pub values: Vec<Slot<V, Idx>>,
+    pub free: Vec<Idx>,
+    /// Number of occupied slots.
+    pub taken: usize,
+    /// Version for newly pushed slots.
+    pub fresh_version: Idx::Version,
+    /// See [`Slab::generation`].
+    pub generation: u64,
+}
+
+impl<T, Idx: SlotInt> KeyData<T, Idx> {
+    /// Used by key types from [`new_key_type!`] to implement [`Key::init`].
+    pub fn new(version: Idx::Version, index: Idx) -> Self {
+        Self {
+            index,
+            version,
+            __phantom: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<T> KeyData<T> {
+    /// Packs the key into a `u64`, version in the high half and index in the
+    /// low half, for handing it across an FFI boundary.
+    pub fn as_ffi(&self) -> u64 {
+        (u64::from(self.version.get()) << 32) | u64::from(self.index)
+    }
+
+    /// Unpacks a key packed by `as_ffi`. Returns `None` if the version half
+    /// is zero, which no key can have.
+    pub fn from_ffi(value: u64) -> Option<Self> {
+        let version = NonZeroU32::new((value >> 32) as u32)?;
+        Some(Self::new(version, value as u32))
+    }
+}
+
+impl<T> DefaultKey<T> {
+    /// See [`KeyData::as_ffi`].
+    pub fn as_ffi(&self) -> u64 {
+        self.data.as_ffi()
+    }
 
-impl<T> PartialEq for KeyData<T> {
+    /// See [`KeyData::from_ffi`].
+    pub fn from_ffi(value: u64) -> Option<Self> {
+        Some(Self {
+            data: KeyData::from_ffi(value)?,
+        })
+    }
+}
+
+impl<T, Idx: SlotInt> PartialEq for KeyData<T, Idx> {
     fn eq(&self, other: &Self) -> bool {
         self.index == other.index && self.version == other.version
     }
 }
 
-impl<T> Eq for KeyData<T> {}
+impl<T, Idx: SlotInt> Eq for KeyData<T, Idx> {}
 
-impl<T> PartialOrd for KeyData<T> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self.index.partial_cmp(&other.index) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
-        }
-        self.version.partial_cmp(&other.version)
+impl<T, Idx: SlotInt> core::hash::Hash for KeyData<T, Idx> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.version.hash(state);
+    }
+}
+
+impl<T, Idx: SlotInt> PartialOrd for KeyData<T, Idx> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl<T> Ord for KeyData<T> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+impl<T, Idx: SlotInt> Ord for KeyData<T, Idx> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
         match self.index.cmp(&other.index) {
             core::cmp::Ordering::Equal => {}
             ord => return ord,
@@ -62,67 +328,118 @@ impl<T> Ord for KeyData<T> {
     }
 }
 
-impl<T> Clone for KeyData<T> {
+impl<T, Idx: SlotInt> Clone for KeyData<T, Idx> {
     fn clone(&self) -> Self {
-        Self {
-            index: self.index,
-            version: self.version,
-            __phantom: core::marker::PhantomData,
-        }
+        *self
     }
 }
 
-impl<T> Copy for KeyData<T> {}
+impl<T, Idx: SlotInt> Copy for KeyData<T, Idx> {}
 
-impl<T> Debug for KeyData<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<T, Idx: SlotInt> Debug for KeyData<T, Idx> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
             "<{}, {}v{}>",
-            std::any::type_name::<T>(),
+            core::any::type_name::<T>(),
             self.index,
             self.version
         )
     }
 }
 
-pub struct Slot<T> {
-    version: NonZeroU32,
+/// A slot's version encodes its state: even versions are occupied and odd
+/// ones vacant. The last odd version, `Idx::MAX`, marks the slot as retired:
+/// it is vacant but can never be reused, since occupying it again would
+/// repeat a version that was already handed out.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Slot<T, Idx: SlotInt = u32> {
+    version: Idx::Version,
     value: Option<T>,
 }
 
-impl<T> Slot<T> {
+impl<T, Idx: SlotInt> Default for Slot<T, Idx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, Idx: SlotInt> Slot<T, Idx> {
     pub const fn new() -> Self {
         Self {
-            version: unsafe { NonZeroU32::new_unchecked(1) },
+            version: Idx::FIRST_VERSION,
             value: None,
         }
     }
 
+    /// Builds a slot from its version and value. Returns `None` unless the
+    /// value is present exactly when the version is even.
+    pub fn from_parts(version: Idx::Version, value: Option<T>) -> Option<Self> {
+        let slot = Self { version, value };
+        (slot.is_occupied() == slot.value.is_some()).then_some(slot)
+    }
+
+    pub fn into_parts(self) -> (Idx::Version, Option<T>) {
+        (self.version, self.value)
+    }
+
+    /// Whether the slot holds a value, which is the case exactly when its
+    /// version is even.
+    #[inline(always)]
+    pub fn is_occupied(&self) -> bool {
+        Idx::get(self.version).to_usize().is_multiple_of(2)
+    }
+
+    /// Whether the slot is empty, which is the case exactly when its
+    /// version is odd.
+    #[inline(always)]
+    pub fn is_vacant(&self) -> bool {
+        !self.is_occupied()
+    }
+
+    #[deprecated(note = "use `is_occupied`")]
     #[inline(always)]
     pub fn occupied(&self) -> bool {
-        self.version.get() % 2 == 0
+        self.is_occupied()
     }
 
+    #[deprecated(note = "use `is_vacant`")]
     #[inline(always)]
     pub fn vacant(&self) -> bool {
-        self.version.get() % 2 != 0
+        self.is_vacant()
+    }
+
+    /// Checks in debug builds that the value is present exactly when the
+    /// version says the slot is occupied.
+    #[inline(always)]
+    fn debug_assert_consistent(&self) {
+        debug_assert_eq!(
+            self.is_occupied(),
+            self.value.is_some(),
+            "slot version {} does not match its value parity",
+            self.version
+        );
     }
 
-    pub fn older_than(&self, version: &NonZeroU32) -> bool {
+    pub fn older_than(&self, version: &Idx::Version) -> bool {
         self.version.lt(version)
     }
 
-    pub fn newer_than(&self, version: &NonZeroU32) -> bool {
+    pub fn newer_than(&self, version: &Idx::Version) -> bool {
         self.version.gt(version)
     }
 
-    pub fn same_version(&self, version: &NonZeroU32) -> bool {
+    pub fn same_version(&self, version: &Idx::Version) -> bool {
         self.version.eq(version)
     }
 
+    pub fn retired(&self) -> bool {
+        Idx::get(self.version) == Idx::MAX
+    }
+
     pub fn update(&mut self, value: T) -> Option<T> {
-        self.version = self.version.checked_add(1).unwrap();
+        self.version = Idx::next_version(self.version).expect("slot is retired");
         self.value.replace(value)
     }
 
@@ -131,77 +448,116 @@ impl<T> Slot<T> {
     }
 
     pub fn vacate(&mut self) -> Option<T> {
-        if self.vacant() {
+        if self.is_vacant() {
             None
         } else {
-            self.version = self.version.checked_add(1).unwrap();
+            self.version = Idx::next_version(self.version).unwrap();
             self.value.take()
         }
     }
 }
 
-pub trait Key<T> {
-    fn data(&self) -> &KeyData<T>;
-    fn init(version: NonZeroU32, idx: u32) -> Self;
+pub trait Key<T, Idx: SlotInt = u32> {
+    fn data(&self) -> &KeyData<T, Idx>;
+    fn init(version: Idx::Version, idx: Idx) -> Self;
+
+    /// Like `init`, for keys minted by a slab whose generation is
+    /// `slab_generation`. Key types that override `validate_generation`
+    /// record it here; the default ignores it and calls `init`.
+    #[inline(always)]
+    fn init_with_generation(version: Idx::Version, idx: Idx, slab_generation: u64) -> Self
+    where
+        Self: Sized,
+    {
+        let _ = slab_generation;
+        Self::init(version, idx)
+    }
 
     fn same_version(&self, other: &Self) -> bool {
         self.version() == other.version()
     }
 
-    fn index(&self) -> u32 {
+    fn index(&self) -> Idx {
         self.data().index
     }
 
-    fn version(&self) -> NonZeroU32 {
+    fn version(&self) -> Idx::Version {
         self.data().version
     }
+
+    /// Whether this key may access a slot at `slot_version`. Key types can
+    /// override this to enforce extra invariants on every slab access.
+    fn validate(&self, slot_version: Idx::Version) -> bool {
+        self.version() == slot_version
+    }
+
+    /// Whether this key may access a slab whose generation is
+    /// `slab_generation`, checked before the version. Key types that record
+    /// the [`Slab::generation`] they were minted in, via
+    /// `init_with_generation`, can reject keys from before a `clear`. The
+    /// default accepts everything and compiles away.
+    #[inline(always)]
+    fn validate_generation(&self, slab_generation: u64) -> bool {
+        let _ = slab_generation;
+        true
+    }
 }
 
+/// A slab of heterogeneous values sharing one key space. Keys carry the
+/// value's type, and each slot records the `TypeId` of the value stored in
+/// it, so looking a key up as any other type yields `None`.
+///
+/// A [`TypedKey`] only reads values of its own type:
+///
+/// ```compile_fail
+/// use tmpkey::AnySlab;
+///
+/// struct A;
+/// struct B;
+///
+/// let mut slab = AnySlab::new();
+/// let a = slab.insert(A);
+/// let b: Option<&B> = slab.get(&a);
+/// ```
+#[derive(Default)]
 pub struct AnySlab {
-    slots: Vec<Slot<Box<dyn std::any::Any>>>,
-    free: Vec<u32>,
-    taken: u32,
+    slab: Slab<DefaultKey<AnyValue>, AnyValue>,
 }
 
-impl Default for AnySlab {
-    fn default() -> Self {
-        Self {
-            slots: vec![],
-            free: vec![],
-            taken: 0,
-        }
+struct AnyValue {
+    type_id: TypeId,
+    value: Box<dyn Any>,
+}
+
+impl AnyValue {
+    fn is<T: 'static>(&self) -> bool {
+        debug_assert_eq!(
+            self.type_id,
+            (*self.value).type_id(),
+            "slot's TypeId doesn't match its value"
+        );
+        self.type_id == TypeId::of::<T>()
     }
 }
 
 impl AnySlab {
     pub fn new() -> Self {
-        Self {
-            slots: vec![],
-            free: vec![],
-            taken: 0,
-        }
+        Self { slab: Slab::new() }
+    }
+
+    fn erase<K: Key<T>, T>(key: &K) -> DefaultKey<AnyValue> {
+        DefaultKey::init(key.version(), key.index())
     }
 
-    pub fn insert<'a, T>(&mut self, value: T) -> DefaultKey<T>
+    pub fn insert<T>(&mut self, value: T) -> TypedKey<T>
     where
         T: 'static,
     {
-        if let Some(free) = self.free.pop() {
-            let slot = &mut self.slots[free as usize];
-            slot.update(Box::new(value));
-            self.taken += 1;
-            DefaultKey::init(slot.version, free)
-        } else {
-            let version = NonZeroU32::new(2).unwrap();
-            let slot = Slot {
-                version,
-                value: Some(Box::new(value) as Box<dyn Any>),
-            };
-            let idx = self.slots.len() as u32;
-            self.slots.push(slot);
-            self.taken += 1;
-            DefaultKey::init(version, idx)
-        }
+        let key = self.slab.insert(AnyValue {
+            type_id: TypeId::of::<T>(),
+            value: Box::new(value),
+        });
+        TypedKey::init(key.version(), key.index())
     }
 
     pub fn get<K, T>(&self, key: &K) -> Option<&T>
@@ -209,11 +565,8 @@ impl AnySlab {
         K: Key<T>,
         T: 'static,
     {
-        let slot = self.slots.get(key.index() as usize)?;
-        slot.version
-            .eq(&key.version())
-            .then(|| slot.value.as_ref().unwrap().downcast_ref::<T>())
-            .flatten()
+        let slot = self.slab.get(Self::erase(key)).filter(|v| v.is::<T>())?;
+        slot.value.downcast_ref::<T>()
     }
 
     pub fn get_mut<K, T>(&mut self, key: &K) -> Option<&mut T>
@@ -221,142 +574,323 @@ impl AnySlab {
         K: Key<T>,
         T: 'static,
     {
-        let slot = self.slots.get_mut(key.index() as usize)?;
-        slot.version
-            .eq(&key.version())
-            .then(|| slot.value.as_mut().unwrap().downcast_mut::<T>())
-            .flatten()
+        let slot = self
+            .slab
+            .get_mut(Self::erase(key))
+            .filter(|v| v.is::<T>())?;
+        slot.value.downcast_mut::<T>()
+    }
+
+    /// Removes and returns the value under `key`. Nothing is removed if the
+    /// stored value is not a `T`.
+    pub fn remove<K, T>(&mut self, key: &K) -> Option<T>
+    where
+        K: Key<T>,
+        T: 'static,
+    {
+        if !self.slab.get(Self::erase(key))?.is::<T>() {
+            return None;
+        }
+        let slot = self.slab.remove(Self::erase(key))?;
+        Some(*slot.value.downcast::<T>().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.slab.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slab.is_empty()
     }
 }
 
-pub struct Slab<K, V>
+/// A slab is `Send` or `Sync` when its keys and values are, so a `&Slab`
+/// can be shared across threads for reading; see also [`Slab::freeze`].
+pub struct Slab<K, V, Idx: SlotInt = u32>
 where
-    K: Key<V>,
+    K: Key<V, Idx>,
 {
-    values: Vec<Slot<V>>,
-    free: Vec<u32>,
-    taken: u32,
-    __phantom: std::marker::PhantomData<K>,
+    values: Vec<Slot<V, Idx>>,
+    free: Vec<Idx>,
+    taken: usize,
+    /// Version for slots pushed onto the end of `values`. Raised past the
+    /// versions of truncated slots so their stale keys can't resolve again.
+    fresh_version: Idx::Version,
+    /// Bumped by `clear`, see [`Key::validate_generation`].
+    generation: u64,
+    counters: Counters,
+    __phantom: core::marker::PhantomData<K>,
 }
 
-impl<'a, V> Default for Slab<DefaultKey<V>, V> {
-    fn default() -> Self {
+impl<K: Key<V, Idx>, V: Clone, Idx: SlotInt> Clone for Slab<K, V, Idx> {
+    fn clone(&self) -> Self {
         Self {
-            values: vec![],
-            free: vec![],
-            taken: 0,
-            __phantom: std::marker::PhantomData,
+            values: self.values.clone(),
+            free: self.free.clone(),
+            taken: self.taken,
+            fresh_version: self.fresh_version,
+            generation: self.generation,
+            counters: self.counters,
+            __phantom: core::marker::PhantomData,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Counters {
+    inserted: u64,
+    recycled: u64,
+    peak_len: usize,
+}
+
+/// A snapshot of a slab's size and running counters, see [`Slab::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SlabStats {
+    pub len: usize,
+    pub slots: usize,
+    pub capacity: usize,
+    /// Slots that hold no value, retired ones included.
+    pub vacant: usize,
+    /// Length of the free list. Every vacant slot that isn't retired should
+    /// be on it exactly once.
+    pub free_len: usize,
+    /// Index of the highest occupied slot, if any.
+    pub highest_occupied: Option<usize>,
+    /// Total number of inserts over the slab's lifetime.
+    pub inserted: u64,
+    /// Number of inserts that reused a previously freed slot.
+    pub recycled: u64,
+    /// Highest `len` the slab has reached.
+    pub peak_len: usize,
+}
+
+/// How many inserts a slab can take without allocating, see
+/// [`Slab::capacity_breakdown`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CapacityBreakdown {
+    /// Inserts that will reuse a freed slot.
+    pub free_slots: usize,
+    /// Inserts that will fit in the backing vector's spare capacity.
+    pub spare_capacity: usize,
+}
+
+impl CapacityBreakdown {
+    pub fn total(&self) -> usize {
+        self.free_slots + self.spare_capacity
+    }
+}
+
+/// Churn between two [`SlabStats`] snapshots, see [`Slab::stats_delta`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatsDelta {
+    pub inserts: u64,
+    pub removes: u64,
+    pub recycled: u64,
+    /// Change in the number of allocated slots.
+    pub growth: isize,
+}
+
+impl<V> Default for Slab<DefaultKey<V>, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertAtError<Idx: SlotInt = u32> {
+    /// The slot at this index holds a value under a different version.
+    Occupied(Idx),
+    /// The version is odd (vacant), or not newer than the vacant slot's, so
+    /// stale keys for the slot could resolve again.
+    InvalidVersion(Idx),
+}
+
+impl<Idx: SlotInt> core::fmt::Display for InsertAtError<Idx> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Occupied(index) => write!(f, "slot at index {index} is occupied"),
+            Self::InvalidVersion(index) => write!(f, "invalid version for index {index}"),
+        }
+    }
+}
+
+impl<Idx: SlotInt> core::error::Error for InsertAtError<Idx> {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FromKeyedError<Idx: SlotInt = u32> {
+    /// Two pairs used the same index.
+    DuplicateIndex(Idx),
+    /// A key at this index had an odd (vacant) version.
+    InvalidVersion(Idx),
+}
+
+impl<Idx: SlotInt> core::fmt::Display for FromKeyedError<Idx> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::DuplicateIndex(index) => write!(f, "duplicate key index {index}"),
+            Self::InvalidVersion(index) => write!(f, "invalid key version at index {index}"),
+        }
+    }
+}
+
+impl<Idx: SlotInt> core::error::Error for FromKeyedError<Idx> {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlabError {
+    /// Every index is in use and none are free.
+    CapacityExhausted,
+}
+
+impl core::fmt::Display for SlabError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::CapacityExhausted => write!(f, "slab capacity exhausted"),
         }
     }
 }
 
-pub struct AccessKey<'a, K, V>
+impl core::error::Error for SlabError {}
+
+/// A key that remembers it was issued for a `Slab<K, V, Idx>` and reads through
+/// a slab passed to [`AccessKey::get`].
+pub struct AccessKey<K, V, Idx: SlotInt = u32>
 where
-    K: Key<V> + Clone,
+    K: Key<V, Idx> + Clone,
 {
     key: K,
-    table: *const Slab<K, V>,
-    __phantom: std::marker::PhantomData<&'a V>,
+    __phantom: core::marker::PhantomData<(V, Idx)>,
 }
 
-impl<'a, K, V> Clone for AccessKey<'a, K, V>
+impl<K, V, Idx: SlotInt> Clone for AccessKey<K, V, Idx>
 where
-    K: Key<V> + Clone,
+    K: Key<V, Idx> + Clone,
 {
     fn clone(&self) -> Self {
         Self {
             key: self.key.clone(),
-            table: self.table,
-            __phantom: self.__phantom,
+            __phantom: core::marker::PhantomData,
         }
     }
 }
 
-impl<'a, K, V> AccessKey<'a, K, V>
+impl<K, V, Idx: SlotInt> AccessKey<K, V, Idx>
 where
-    K: Key<V> + Clone,
+    K: Key<V, Idx> + Clone,
 {
-    pub fn new(key: K, table: &Slab<K, V>) -> Self {
+    pub fn new(key: K) -> Self {
         Self {
             key,
-            table: table as *const Slab<K, V>,
-            __phantom: std::marker::PhantomData,
+            __phantom: core::marker::PhantomData,
         }
     }
 
-    pub fn get(&self) -> Option<&V> {
-        let table = unsafe { &*self.table };
-        let slot = &table.values[self.data().index as usize];
-        if slot.version == self.data().version {
-            slot.value.as_ref()
-        } else {
-            None
-        }
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn get<'a>(&self, table: &'a Slab<K, V, Idx>) -> Option<&'a V> {
+        table.get(self.key.clone())
     }
 }
 
-impl<'a, K, V> Key<V> for AccessKey<'a, K, V>
+impl<K, V, Idx: SlotInt> Key<V, Idx> for AccessKey<K, V, Idx>
 where
-    K: Key<V> + Clone,
+    K: Key<V, Idx> + Clone,
 {
-    fn data(&self) -> &KeyData<V> {
+    fn data(&self) -> &KeyData<V, Idx> {
         self.key.data()
     }
 
-    fn init(_: NonZeroU32, _: u32) -> Self {
-        panic!("init should not be used for AccessKey")
+    fn init(version: Idx::Version, index: Idx) -> Self {
+        Self::new(K::init(version, index))
+    }
+
+    fn init_with_generation(version: Idx::Version, index: Idx, slab_generation: u64) -> Self {
+        Self::new(K::init_with_generation(version, index, slab_generation))
     }
 }
 
-impl<'a, K, V> Debug for AccessKey<'a, K, V>
+impl<K, V, Idx: SlotInt> Debug for AccessKey<K, V, Idx>
 where
-    K: Key<V> + Clone + Debug,
+    K: Key<V, Idx> + Clone + Debug,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("AccessKey").field("key", &self.key).finish()
     }
 }
 
-impl<K: Key<V> + Clone, V> Slab<K, V> {
-    pub fn insert<'a>(&mut self, value: V) -> K
+impl<K: Key<V, Idx> + Clone, V, Idx: SlotInt> Slab<K, V, Idx> {
+    /// Panics if the slab already holds `Idx::MAX` slots and none are free;
+    /// see `try_insert`.
+    pub fn insert(&mut self, value: V) -> K
     where
-        K: Key<V>,
+        K: Key<V, Idx>,
     {
-        if let Some(index) = self.free.pop() {
-            let slot = &mut self.values[index as usize];
-            slot.value = Some(value);
-            slot.version = slot.version.saturating_add(1);
-            self.taken += 1;
-            K::init(slot.version, index)
-        } else {
-            let index = self.values.len() as u32;
-            let version = unsafe { NonZeroU32::new_unchecked(2) };
-            self.values.push(Slot {
-                version,
-                value: Some(value),
-            });
-            self.taken += 1;
-            K::init(version, index)
+        self.insert_with_key(|_| value)
+    }
+
+    /// Like `insert`, but returns an error instead of panicking when there
+    /// is no index left to store the value under.
+    pub fn try_insert(&mut self, value: V) -> Result<K, SlabError> {
+        self.try_insert_within(value, Idx::MAX.to_usize())
+    }
+
+    fn try_insert_within(&mut self, value: V, max_slots: usize) -> Result<K, SlabError> {
+        if self.free.is_empty() {
+            match Idx::try_from_usize(self.values.len()) {
+                Some(index) if index.to_usize() < max_slots => {}
+                _ => return Err(SlabError::CapacityExhausted),
+            }
         }
+        Ok(self.insert(value))
     }
 
-    pub fn insert_with_access<'a>(&mut self, value: V) -> AccessKey<K, V> {
+    /// Inserts the value returned by `f`, which is called with the key the
+    /// value will be stored under.
+    pub fn insert_with_key<F: FnOnce(K) -> V>(&mut self, f: F) -> K {
+        let (index, version) = match self.free.last() {
+            Some(&index) => {
+                let version = self.values[index.to_usize()].version;
+                (
+                    index,
+                    Idx::next_version(version).expect("free list contains a retired slot"),
+                )
+            }
+            None => {
+                let index = Idx::try_from_usize(self.values.len())
+                    .filter(|&index| index < Idx::MAX)
+                    .expect("slab capacity exhausted: every index is in use and none are free");
+                (index, self.fresh_version)
+            }
+        };
+        let value = f(K::init_with_generation(version, index, self.generation));
         if let Some(index) = self.free.pop() {
-            let slot = &mut self.values[index as usize];
+            let slot = &mut self.values[index.to_usize()];
+            slot.debug_assert_consistent();
             slot.value = Some(value);
-            slot.version = slot.version.saturating_add(1);
-            self.taken += 1;
-            AccessKey::new(K::init(slot.version, index), self)
+            slot.version = version;
+            self.counters.recycled += 1;
         } else {
-            let index = self.values.len() as u32;
-            let version = unsafe { NonZeroU32::new_unchecked(2) };
             self.values.push(Slot {
                 version,
                 value: Some(value),
             });
-            self.taken += 1;
-            AccessKey::new(K::init(version, index), self)
         }
+        self.taken += 1;
+        self.counters.inserted += 1;
+        self.counters.peak_len = self.counters.peak_len.max(self.taken);
+        K::init_with_generation(version, index, self.generation)
+    }
+
+    /// Like `extend`, but returns the keys of the inserted values in order.
+    pub fn extend_keys<I: IntoIterator<Item = V>>(&mut self, iter: I) -> Vec<K> {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        iter.map(|value| self.insert(value)).collect()
+    }
+
+    pub fn insert_with_access(&mut self, value: V) -> AccessKey<K, V, Idx> {
+        AccessKey::new(self.insert(value))
     }
 
     pub fn new() -> Self {
@@ -364,392 +898,4663 @@ impl<K: Key<V> + Clone, V> Slab<K, V> {
             values: vec![],
             free: vec![],
             taken: 0,
-            __phantom: std::marker::PhantomData,
+            fresh_version: Idx::FRESH_VERSION,
+            generation: 0,
+            counters: Counters::default(),
+            __phantom: core::marker::PhantomData,
         }
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             values: Vec::with_capacity(capacity),
-            free: Vec::new(),
-            taken: 0,
-            __phantom: std::marker::PhantomData,
+            ..Self::new()
         }
     }
 
-    pub fn remove(&mut self, key: K) -> Option<V> {
-        let slot = &mut self.values[key.index() as usize];
-        slot.same_version(&key.version())
-            .then(|| {
-                let value = slot.value.take();
-                self.free.push(key.index());
-                self.taken -= 1;
-                slot.version = slot.version.saturating_add(1);
-                value
-            })
-            .flatten()
-    }
-
-    pub fn get(&self, key: K) -> Option<&V> {
-        let slot = &self.values[key.index() as usize];
-        slot.same_version(&key.version())
-            .then(|| slot.value.as_ref())
-            .flatten()
+    /// Creates a slab with `n` vacant slots already allocated, so that the
+    /// first `n` inserts reuse them in index order `0..n` without growing.
+    pub fn with_prepared_slots(n: usize) -> Self {
+        Self {
+            values: (0..n).map(|_| Slot::new()).collect(),
+            free: (0..n).rev().map(Idx::from_usize).collect(),
+            ..Self::new()
+        }
     }
 
-    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
-        let slot = &mut self.values[key.index() as usize];
-        slot.same_version(&key.version())
-            .then(|| slot.value.as_mut())
-            .flatten()
+    /// Builds a slab in which every `(key, value)` pair resolves under its
+    /// original key. Indices not covered by any pair become vacant slots in
+    /// the free list, lowest index first.
+    pub fn from_keyed(
+        pairs: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, FromKeyedError<Idx>> {
+        let mut slab = Self::new();
+        for (key, value) in pairs {
+            let index = key.index();
+            if !Idx::get(key.version()).to_usize().is_multiple_of(2) {
+                return Err(FromKeyedError::InvalidVersion(index));
+            }
+            if index.to_usize() >= slab.values.len() {
+                slab.values.resize_with(index.to_usize() + 1, Slot::new);
+            }
+            let slot = &mut slab.values[index.to_usize()];
+            if slot.is_occupied() {
+                return Err(FromKeyedError::DuplicateIndex(index));
+            }
+            slot.version = key.version();
+            slot.value = Some(value);
+            slab.taken += 1;
+        }
+        slab.free = (0..slab.values.len())
+            .rev()
+            .filter(|&i| slab.values[i].is_vacant())
+            .map(Idx::from_usize)
+            .collect();
+        slab.counters.inserted = slab.taken as u64;
+        slab.counters.peak_len = slab.taken;
+        Ok(slab)
     }
 
-    pub fn len(&self) -> usize {
-        self.taken as usize
+    /// Builds a slab directly from its parts, as returned by
+    /// `into_raw_parts`. Passing an older `fresh_version` than the one
+    /// returned lets stale keys to slots truncated by `shrink_to_fit` resolve
+    /// again once new slots are pushed at those indices; an older
+    /// `generation` does the same for keys rejected by
+    /// [`Key::validate_generation`].
+    ///
+    /// # Safety
+    ///
+    /// `taken` must equal the number of occupied slots, and `free` must list
+    /// every vacant, non-retired slot at most once and nothing else.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fresh_version` is odd.
+    pub unsafe fn from_raw_parts(parts: RawParts<V, Idx>) -> Self {
+        let RawParts {
+            values,
+            free,
+            taken,
+            fresh_version,
+            generation,
+        } = parts;
+        assert!(
+            Idx::get(fresh_version).to_usize().is_multiple_of(2),
+            "fresh_version must be even"
+        );
+        let slab = Self {
+            values,
+            free,
+            taken,
+            fresh_version,
+            generation,
+            counters: Counters {
+                inserted: taken as u64,
+                recycled: 0,
+                peak_len: taken,
+            },
+            ..Self::new()
+        };
+        debug_assert_eq!(slab.validate(), Ok(()));
+        slab
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.taken == 0
+    /// Wraps the slab so it can only be read, e.g. to share it across
+    /// threads after setup.
+    pub fn freeze(self) -> FrozenSlab<K, V, Idx> {
+        FrozenSlab { slab: self }
     }
 
-    pub fn capacity(&self) -> usize {
-        self.values.capacity()
+    /// Splits the slab into its parts, see `from_raw_parts`.
+    pub fn into_raw_parts(self) -> RawParts<V, Idx> {
+        RawParts {
+            values: self.values,
+            free: self.free,
+            taken: self.taken,
+            fresh_version: self.fresh_version,
+            generation: self.generation,
+        }
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (K, &V)> {
-        self.values.iter().enumerate().filter_map(|(i, v)| {
-            v.occupied()
-                .then(|| (K::init(v.version, i as u32), v.value.as_ref().unwrap()))
-        })
+    /// Builds a slab with the same slots, versions and free list, applying
+    /// `f` to every live value. Each key of this slab maps to the key with
+    /// the same index and version in the new one, so a key type that
+    /// implements `Key` for both value types resolves in either.
+    pub fn map_values<K2, V2, F>(&self, mut f: F) -> Slab<K2, V2, Idx>
+    where
+        K2: Key<V2, Idx>,
+        F: FnMut(&V) -> V2,
+    {
+        Slab {
+            values: self
+                .values
+                .iter()
+                .map(|slot| Slot {
+                    version: slot.version,
+                    value: slot.value.as_ref().map(&mut f),
+                })
+                .collect(),
+            free: self.free.clone(),
+            taken: self.taken,
+            fresh_version: self.fresh_version,
+            generation: self.generation,
+            counters: self.counters,
+            __phantom: core::marker::PhantomData,
+        }
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (K, &mut V)> {
-        self.values.iter_mut().enumerate().filter_map(|(i, v)| {
-            v.occupied()
-                .then(|| (K::init(v.version, i as u32), v.value.as_mut().unwrap()))
-        })
+    /// Stores `value` at `index` under `version` and returns its key, growing
+    /// the slab if needed. Slots added in between become vacant and join the
+    /// free list. If the slot already holds a value under `version`, that
+    /// value is replaced.
+    ///
+    /// Slots past the end start out at the version `insert` would give them,
+    /// so keys to slots dropped by `shrink_to_fit` stay stale;
+    /// [`InsertAtError::InvalidVersion`] is returned for an `index` past the
+    /// end with an older `version`.
+    pub fn insert_at(
+        &mut self,
+        index: Idx,
+        version: Idx::Version,
+        value: V,
+    ) -> Result<K, InsertAtError<Idx>> {
+        if !Idx::get(version).to_usize().is_multiple_of(2) {
+            return Err(InsertAtError::InvalidVersion(index));
+        }
+        let i = index.to_usize();
+        if let Some(slot) = self.values.get_mut(i) {
+            if slot.is_occupied() {
+                if !slot.same_version(&version) {
+                    return Err(InsertAtError::Occupied(index));
+                }
+                slot.swap(value);
+                return Ok(K::init_with_generation(version, index, self.generation));
+            }
+            if !slot.older_than(&version) {
+                return Err(InsertAtError::InvalidVersion(index));
+            }
+            self.free.retain(|&free| free != index);
+        } else {
+            if version < self.fresh_version {
+                return Err(InsertAtError::InvalidVersion(index));
+            }
+            let len = self.values.len();
+            let vacant = Idx::prev_version(self.fresh_version).unwrap();
+            self.values.resize_with(i + 1, || Slot {
+                version: vacant,
+                value: None,
+            });
+            self.free.extend((len..i).rev().map(Idx::from_usize));
+        }
+        let slot = &mut self.values[i];
+        slot.version = version;
+        slot.value = Some(value);
+        self.taken += 1;
+        self.counters.inserted += 1;
+        self.counters.peak_len = self.counters.peak_len.max(self.taken);
+        Ok(K::init_with_generation(version, index, self.generation))
     }
 
-    pub fn values(&self) -> impl Iterator<Item = &V> {
+    /// The occupied slot `key` points to, if the key is in bounds and
+    /// [`Key::validate`] accepts the slot's version.
+    fn slot(&self, key: &K) -> Option<&Slot<V, Idx>> {
+        if !key.validate_generation(self.generation) {
+            return None;
+        }
         self.values
-            .iter()
-            .filter_map(|v| v.vacant().then(|| v.value.as_ref()).flatten())
+            .get(key.index().to_usize())
+            .filter(|slot| slot.is_occupied() && key.validate(slot.version))
     }
 
-    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+    fn slot_mut(&mut self, key: &K) -> Option<&mut Slot<V, Idx>> {
+        if !key.validate_generation(self.generation) {
+            return None;
+        }
         self.values
-            .iter_mut()
-            .filter_map(|v| v.vacant().then(|| v.value.as_mut()))
-            .flatten()
+            .get_mut(key.index().to_usize())
+            .filter(|slot| slot.is_occupied() && key.validate(slot.version))
     }
 
-    pub fn clear(&mut self) {
-        self.values.iter_mut().enumerate().for_each(|(i, v)| {
-            v.occupied().then(|| {
-                v.version = v.version.saturating_add(1);
-                v.value.take();
-                self.free.push(i as u32);
-            });
-        });
-        self.taken = 0;
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        self.slot(&key)?;
+        self.release(key.index())
     }
 
-    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
-        let freed = self
-            .values
-            .iter_mut()
-            .enumerate()
-            .filter_map(|(i, v)| {
-                v.occupied().then(|| {
-                    let key = K::init(v.version, i as u32);
-                    f(&key, v.value.as_mut().unwrap()).not().then(|| {
-                        self.free.push(i as u32);
-                        v.vacate()
-                    })
-                })
-            })
-            .count();
-        self.taken = (self.values.len() - freed) as u32;
+    /// Like `remove`, but also returns the version the slot advanced to.
+    pub fn remove_versioned(&mut self, key: K) -> Option<(V, Idx::Version)> {
+        let value = self.remove(key.clone())?;
+        Some((value, self.values[key.index().to_usize()].version))
     }
-}
 
-impl<K: Key<V>, V> IntoIterator for Slab<K, V> {
-    type Item = V;
+    /// Removes the entries for every key in `keys` and returns how many were
+    /// removed. Keys that are stale, including repeats of a key already
+    /// removed earlier in the batch, are skipped.
+    pub fn remove_many<I: IntoIterator<Item = K>>(&mut self, keys: I) -> usize {
+        keys.into_iter()
+            .filter(|key| self.remove(key.clone()).is_some())
+            .count()
+    }
 
-    type IntoIter = FilterMap<std::vec::IntoIter<Slot<V>>, Box<dyn FnMut(Slot<V>) -> Option<V>>>;
+    /// Vacates the occupied slot at `index` and hands it to the free list,
+    /// unless its version is exhausted, in which case the slot is retired.
+    fn release(&mut self, index: Idx) -> Option<V> {
+        let slot = &mut self.values[index.to_usize()];
+        slot.debug_assert_consistent();
+        let value = slot.vacate();
+        if !slot.retired() {
+            self.free.push(index);
+        }
+        self.taken -= 1;
+        value
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.values.into_iter().filter_map(Box::new(|v: Slot<V>| {
-            if v.version.get() % 2 == 0 {
-                v.value
-            } else {
-                None
-            }
-        }))
+    /// Removes every live entry, yielding them with their keys while keeping
+    /// the allocation. Entries not consumed by the time the iterator is
+    /// dropped are removed as well.
+    pub fn drain(&mut self) -> Drain<'_, K, V, Idx> {
+        Drain {
+            slab: self,
+            index: 0,
+        }
     }
-}
 
-impl<K: Key<V> + Clone, V: Debug> Debug for Slab<K, V> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut dbg = f.debug_struct(&format!(
-            "Slab<{}, {}>",
-            std::any::type_name::<K>(),
-            std::any::type_name::<V>()
-        ));
-        self.values
-            .iter()
-            .enumerate()
-            .filter(|(_, v)| v.version.get() % 2 == 0)
-            .for_each(|(i, v)| {
-                dbg.field(&format!("{}v{}", i, v.version), v.value.as_ref().unwrap());
-            });
-        dbg.finish()
+    /// Returns a cursor positioned at the first live entry, which can remove
+    /// entries as it walks the slab in index order.
+    pub fn cursor_mut(&mut self) -> CursorMut<'_, K, V, Idx> {
+        let mut cursor = CursorMut {
+            slab: self,
+            index: 0,
+        };
+        cursor.seek(0);
+        cursor
     }
-}
 
-pub struct AssociatedData<K: Key<N>, V, N> {
-    items: Vec<Slot<V>>,
-    taken: u32,
-    __phantom: std::marker::PhantomData<(K, N)>,
-}
+    /// Returns the value for `key` without checking that it is in bounds or
+    /// that its version matches.
+    ///
+    /// # Safety
+    ///
+    /// `key` must be live in this slab: issued by it and not yet removed.
+    pub unsafe fn get_unchecked(&self, key: K) -> &V {
+        let slot = unsafe { self.values.get_unchecked(key.index().to_usize()) };
+        unsafe { slot.value.as_ref().unwrap_unchecked() }
+    }
 
-impl<K: Key<N>, V: Debug, N> Debug for AssociatedData<K, V, N> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut dbg = f.debug_struct(&format!(
-            "AssociatedData<{}, {}>",
-            std::any::type_name::<K>(),
-            std::any::type_name::<V>()
-        ));
-        self.items
-            .iter()
-            .enumerate()
-            .filter(|(_, v)| v.version.get() % 2 == 0)
-            .for_each(|(i, v)| {
-                dbg.field(&format!("{}v{}", i, v.version), v.value.as_ref().unwrap());
-            });
-        dbg.finish()
+    /// Mutable counterpart to [`Slab::get_unchecked`].
+    ///
+    /// # Safety
+    ///
+    /// `key` must be live in this slab: issued by it and not yet removed.
+    pub unsafe fn get_unchecked_mut(&mut self, key: K) -> &mut V {
+        let slot = unsafe { self.values.get_unchecked_mut(key.index().to_usize()) };
+        unsafe { slot.value.as_mut().unwrap_unchecked() }
     }
-}
 
-impl<K: Key<N>, V, N> AssociatedData<K, V, N> {
-    pub fn new() -> Self {
-        Self {
-            items: vec![],
-            taken: 0,
-            __phantom: std::marker::PhantomData,
-        }
+    pub fn get(&self, key: K) -> Option<&V> {
+        self.slot(&key)?.value.as_ref()
     }
 
-    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        let data = key.data();
-        let index = data.index as usize;
-        if index >= self.items.len() {
-            self.items
-                .extend((self.items.len()..=index as usize).map(|_| Slot::new()));
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        self.slot_mut(&key)?.value.as_mut()
+    }
+
+    /// Returns the value under `key` along with the slab's own key for it,
+    /// rebuilt from the slot. A stale key yields `None`, not the current
+    /// occupant.
+    pub fn get_key_value(&self, key: K) -> Option<(K, &V)> {
+        let slot = self.slot(&key)?;
+        Some((
+            K::init_with_generation(slot.version, key.index(), self.generation),
+            slot.value.as_ref()?,
+        ))
+    }
+
+    /// Replaces the value under `key` and returns the old one. The version
+    /// is left as is, so `key` stays valid. Nothing is inserted if `key` is
+    /// not live.
+    pub fn replace(&mut self, key: K, value: V) -> Option<V> {
+        self.slot_mut(&key)?.swap(value)
+    }
+
+    pub fn contains_key(&self, key: K) -> bool {
+        self.slot(&key).is_some()
+    }
+
+    /// Returns the live key for the slot at `index`, carrying the slot's
+    /// current version, or `None` if there is no such slot or it is vacant.
+    pub fn key_at_index(&self, index: Idx) -> Option<K> {
+        self.values
+            .get(index.to_usize())
+            .filter(|slot| slot.is_occupied())
+            .map(|slot| K::init_with_generation(slot.version, index, self.generation))
+    }
+
+    /// Mutably borrows several entries at once. Returns `None` if any key is
+    /// not live or two keys point to the same slot.
+    pub fn get_disjoint_mut<const N: usize>(&mut self, keys: [K; N]) -> Option<[&mut V; N]> {
+        for (i, key) in keys.iter().enumerate() {
+            self.slot(key)?;
+            if keys[..i].iter().any(|k| k.index() == key.index()) {
+                return None;
+            }
         }
-        let slot = &mut self.items[index];
-        if slot.vacant() {
-            self.taken += 1;
-        } else if slot.same_version(&data.version) {
-            return slot.swap(value);
-        } else if slot.newer_than(&data.version) {
-            // Don't replace newer versions
+        let slots = self.values.as_mut_ptr();
+        // SAFETY: every index is in bounds and occupied, and no two are equal,
+        // so the references are to distinct, initialized slots.
+        Some(keys.map(|k| unsafe { (*slots.add(k.index().to_usize())).value.as_mut().unwrap() }))
+    }
+
+    /// Borrows the values under two distinct keys mutably at once. Returns
+    /// `None` if either key is not live or both refer to the same slot.
+    pub fn get2_mut(&mut self, a: K, b: K) -> Option<(&mut V, &mut V)> {
+        let (i, j) = (a.index().to_usize(), b.index().to_usize());
+        if i == j {
             return None;
         }
-        slot.version = data.version;
-        slot.value = Some(value);
-        None
+        self.slot(&a)?;
+        self.slot(&b)?;
+        let slots = self.values.as_mut_ptr();
+        // SAFETY: both indices are in bounds, occupied and distinct, so the
+        // references are to different, initialized slots.
+        unsafe {
+            Some((
+                (*slots.add(i)).value.as_mut().unwrap(),
+                (*slots.add(j)).value.as_mut().unwrap(),
+            ))
+        }
     }
 
-    pub fn remove(&mut self, key: K) -> Option<V> {
-        let data = key.data();
-        let index = data.index as usize;
-        if index >= self.items.len() {
-            return None;
+    /// Exchanges the values stored under `a` and `b`, leaving both keys
+    /// valid. Returns `false` without changing anything if either key is
+    /// not live.
+    pub fn swap(&mut self, a: K, b: K) -> bool {
+        if self.slot(&a).is_none() || self.slot(&b).is_none() {
+            return false;
         }
-        let slot = &mut self.items[index];
-        if slot.occupied() && slot.same_version(&data.version) {
-            self.taken -= 1;
-            return slot.vacate();
+        let (a, b) = (a.index().to_usize(), b.index().to_usize());
+        if a != b {
+            let value = self.values[a].value.take();
+            let value = core::mem::replace(&mut self.values[b].value, value);
+            self.values[a].value = value;
         }
-        None
+        true
     }
 
-    pub fn get(&self, key: K) -> Option<&V> {
-        let data = key.data();
-        let index = data.index as usize;
-        if index >= self.items.len() {
-            return None;
+    pub fn len(&self) -> usize {
+        self.taken
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taken == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.values.capacity()
+    }
+
+    /// Reserves room for at least `additional` more inserts without
+    /// reallocating, counting slots already waiting in the free list.
+    pub fn reserve(&mut self, additional: usize) {
+        self.values
+            .reserve(additional.saturating_sub(self.free.len()));
+    }
+
+    /// Drops the vacant slots at the end of the slab and releases unused
+    /// memory. Slots before the last occupied one are kept, so every live key
+    /// stays valid.
+    pub fn shrink_to_fit(&mut self) {
+        self.truncate_vacant_tail();
+        self.values.shrink_to_fit();
+        self.free.shrink_to_fit();
+    }
+
+    /// Drops the vacant slots at the end of the slab, like `shrink_to_fit`,
+    /// but keeps the allocation. Their indices are popped off the top of the
+    /// free list, so when the trailing slots were the most recently freed,
+    /// as after removing entries from the end, this takes time proportional
+    /// to the number of slots dropped. Otherwise the whole free list is
+    /// scanned to remove them.
+    pub fn truncate_vacant_tail(&mut self) {
+        let mut end = self.values.len();
+        while end > 0 {
+            let slot = &self.values[end - 1];
+            match Idx::next_version(slot.version) {
+                Some(next) if slot.is_vacant() => {
+                    self.fresh_version = self.fresh_version.max(next);
+                    end -= 1;
+                }
+                _ => break,
+            }
         }
-        let slot = &self.items[index];
-        if slot.occupied() && slot.same_version(&data.version) {
-            return slot.value.as_ref();
+        let mut dropped = self.values.len() - end;
+        while dropped > 0 && self.free.last().is_some_and(|i| i.to_usize() >= end) {
+            self.free.pop();
+            dropped -= 1;
         }
-        None
+        if dropped > 0 {
+            self.free.retain(|&i| i.to_usize() < end);
+        }
+        self.values.truncate(end);
     }
 
-    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
-        let data = key.data();
-        let index = data.index as usize;
-        if index >= self.items.len() {
-            return None;
+    /// Moves every live value to the lowest available index, keeping their
+    /// order, and returns a map from each live key to its new key.
+    ///
+    /// Moved values get a new version at their new index, so old keys to
+    /// them go stale instead of aliasing another entry. Retired slots can't
+    /// be reused and stay where they are. The vacant slots left at the end
+    /// are kept for reuse; call `shrink_to_fit` afterwards to release them.
+    pub fn compact(&mut self) -> AssociatedData<K, K, V, Idx> {
+        let mut remap = AssociatedData::new();
+        let mut next = 0;
+        for i in 0..self.values.len() {
+            if self.values[i].is_vacant() {
+                continue;
+            }
+            while self.values[next].retired() {
+                next += 1;
+            }
+            let old = K::init_with_generation(
+                self.values[i].version,
+                Idx::from_usize(i),
+                self.generation,
+            );
+            if next == i {
+                remap.insert(old.clone(), old);
+            } else {
+                let value = self.values[i].vacate().unwrap();
+                let slot = &mut self.values[next];
+                slot.update(value);
+                remap.insert(
+                    old,
+                    K::init_with_generation(slot.version, Idx::from_usize(next), self.generation),
+                );
+            }
+            next += 1;
         }
-        let slot = &mut self.items[index];
-        if slot.occupied() && slot.same_version(&data.version) {
-            return slot.value.as_mut();
+        self.free.clear();
+        self.free.extend(
+            (next..self.values.len())
+                .rev()
+                .filter(|&i| !self.values[i].retired())
+                .map(|i| Idx::from_usize(i)),
+        );
+        remap
+    }
+
+    /// Number of vacant slots waiting to be reused by inserts.
+    pub fn free_list_len(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Sorts the free list so the next inserts reuse the lowest vacant
+    /// indices first, keeping live entries packed toward the front.
+    pub fn defragment_free_list(&mut self) {
+        // Inserts pop from the back, so the lowest index goes last.
+        self.free.sort_unstable_by(|a, b| b.cmp(a));
+    }
+
+    /// Counts slots whose versions ran out, which stay allocated but are
+    /// never reused.
+    pub fn retired_slots(&self) -> usize {
+        self.values.iter().filter(|v| v.retired()).count()
+    }
+
+    pub fn capacity_breakdown(&self) -> CapacityBreakdown {
+        CapacityBreakdown {
+            free_slots: self.free.len(),
+            spare_capacity: self.values.capacity() - self.values.len(),
         }
-        None
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (K, &V)> {
-        self.items.iter().enumerate().filter_map(|(i, v)| {
-            v.occupied().then(|| {
-                let key = K::init(v.version, i as u32);
-                (key, v.value.as_ref().unwrap())
-            })
-        })
+    /// Iterates live entries in ascending index order. Since freed slots are
+    /// reused, this can differ from insertion order.
+    pub fn iter(&self) -> Iter<'_, K, V, Idx> {
+        Iter {
+            slots: self.values.iter().enumerate(),
+            remaining: self.len(),
+            generation: self.generation,
+            __phantom: core::marker::PhantomData,
+        }
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (K, &mut V)> {
-        self.items.iter_mut().enumerate().filter_map(|(i, v)| {
-            v.occupied().then(|| {
-                let key = K::init(v.version, i as u32);
-                (key, v.value.as_mut().unwrap())
-            })
-        })
+    /// Like `iter`, in ascending index order.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, Idx> {
+        IterMut {
+            remaining: self.len(),
+            generation: self.generation,
+            slots: self.values.iter_mut().enumerate(),
+            __phantom: core::marker::PhantomData,
+        }
     }
 
-    pub fn values(&self) -> impl Iterator<Item = &V> {
-        self.items.iter().filter_map(|v| v.value.as_ref())
+    /// Collects the live entries sorted by `compare` on their values. The
+    /// sort is stable, so equal values stay in index order.
+    pub fn iter_sorted_by<F>(&self, mut compare: F) -> Vec<(K, &V)>
+    where
+        F: FnMut(&V, &V) -> core::cmp::Ordering,
+    {
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort_by(|(_, a), (_, b)| compare(a, b));
+        entries
     }
 
-    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
-        self.items.iter_mut().filter_map(|v| v.value.as_mut())
+    /// Iterates the keys of all live entries, without borrowing their values.
+    pub fn keys(&self) -> Keys<'_, K, V, Idx> {
+        Keys(self.iter())
     }
 
-    pub fn len(&self) -> usize {
-        self.taken as usize
+    /// Iterates the raw indices of all occupied slots in ascending order,
+    /// for indexing arrays kept alongside the slab.
+    pub fn occupied_indices(&self) -> impl Iterator<Item = Idx> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_occupied())
+            .map(|(i, _)| Idx::from_usize(i))
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.taken == 0
+    /// Replaces the contents of `buf` with the keys of all live entries in
+    /// index order, reusing its allocation.
+    pub fn collect_keys_into(&self, buf: &mut Vec<K>) {
+        buf.clear();
+        buf.extend(self.keys());
     }
 
-    pub fn clear(&mut self) {
-        self.items.iter_mut().for_each(|v| {
-            v.value.take();
-            v.version = v.version.saturating_add(1);
-        });
-        self.taken = 0;
+    /// Iterates live entries with an index strictly greater than `after`'s,
+    /// or from the start if `after` is `None`. Useful as a pagination cursor.
+    pub fn iter_from(&self, after: Option<K>) -> impl Iterator<Item = (K, &V)> {
+        let start = after.map_or(0, |k| k.index().to_usize() + 1);
+        self.values
+            .iter()
+            .enumerate()
+            .skip(start)
+            .filter(|(_, v)| v.is_occupied())
+            .map(|(i, v)| {
+                (
+                    K::init_with_generation(v.version, Idx::from_usize(i), self.generation),
+                    v.value.as_ref().unwrap(),
+                )
+            })
     }
 
-    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
-        let freed = self
-            .items
-            .iter_mut()
+    /// Consumes the slab, yielding every live entry along with its key.
+    pub fn into_iter_with_keys(self) -> IntoIter<K, V, Idx> {
+        IntoIter {
+            remaining: self.len(),
+            generation: self.generation,
+            slots: self.values.into_iter().enumerate(),
+            __phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Returns the key of the live entry with the lowest index. This scans
+    /// past any vacant slots at the front, so it is O(n) in the worst case.
+    pub fn first_key(&self) -> Option<K> {
+        self.keys().next()
+    }
+
+    /// Returns the key of the live entry with the highest index. This scans
+    /// past any vacant slots at the back, so it is O(n) in the worst case.
+    pub fn last_key(&self) -> Option<K> {
+        self.keys().next_back()
+    }
+
+    /// Iterates live entries starting at `key`'s index, inclusive, so a
+    /// checkpointed key resumes where it left off. Unlike `iter_from`, the
+    /// slot at `key`'s index is included, even if it now holds a newer entry.
+    pub fn iter_at(&self, key: K) -> impl Iterator<Item = (K, &V)> {
+        self.values
+            .iter()
             .enumerate()
-            .filter_map(|(i, v)| {
-                v.occupied().then(|| {
-                    let key = K::init(v.version, i as u32);
-                    f(&key, v.value.as_mut().unwrap()).not().then(|| {
-                        v.vacate();
-                        1
-                    })
-                })
+            .skip(key.index().to_usize())
+            .filter(|(_, v)| v.is_occupied())
+            .map(|(i, v)| {
+                (
+                    K::init_with_generation(v.version, Idx::from_usize(i), self.generation),
+                    v.value.as_ref().unwrap(),
+                )
             })
-            .count();
-        self.taken = (self.items.len() - freed) as u32;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::eprintln;
+    pub fn values(&self) -> Values<'_, K, V, Idx> {
+        Values(self.iter())
+    }
 
-    use super::*;
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V, Idx> {
+        ValuesMut(self.iter_mut())
+    }
 
-    #[test]
-    fn any() {
-        let mut map = AnySlab::new();
-        let mut keys = vec![];
-        let mut keys2 = vec![];
-        (0..10).for_each(|i| {
-            let k = map.insert(i);
-            keys.push(k);
-        });
-        (0..10).for_each(|i| {
-            let k = map.insert(format!("test {}", i));
-            keys2.push(k);
-        });
-        for (i, k) in keys.iter().enumerate() {
-            assert_eq!(map.get(k), Some(&i));
-        }
-        for k in keys2.iter() {
-            let v = map.get(k);
-            eprintln!("{v:?}");
-            assert!(v.is_some(), "{v:?}");
-        }
-        assert!(false)
+    /// Like `iter_mut`, but skips every slot whose index matches one of the
+    /// `exclude` keys, so those entries can stay borrowed elsewhere.
+    pub fn values_mut_except<'a>(
+        &'a mut self,
+        exclude: &'a [K],
+    ) -> impl Iterator<Item = (K, &'a mut V)> + 'a {
+        self.iter_mut()
+            .filter(move |(k, _)| !exclude.iter().any(|e| e.index() == k.index()))
     }
 
-    #[test]
-    fn associated() {
-        let mut map = Slab::default();
-        let mut associated = AssociatedData::new();
-        let mut keys = vec![];
-        (0..10).for_each(|i| {
-            let k = map.insert(i);
-            if i % 2 == 0 {
-                associated.insert(k, map.len());
+    /// Removes every entry, keeping the allocation. The free list is rebuilt
+    /// from scratch so that later inserts refill slots from index 0 upwards.
+    pub fn clear(&mut self) {
+        self.free.clear();
+        self.values.iter_mut().enumerate().rev().for_each(|(i, v)| {
+            v.debug_assert_consistent();
+            v.vacate();
+            if !v.retired() {
+                self.free.push(Idx::from_usize(i));
             }
-            keys.push(k);
         });
-        assert_eq!(associated.len(), 5, "{associated:?}");
-        assert_eq!(associated.get(keys[0]), Some(&1), "{associated:?}");
-        // assert!(false, "\n{associated:#?}\n{map:#?}")
+        self.taken = 0;
+        self.generation += 1;
     }
 
-    #[test]
-    fn into_iter() {
-        let mut map = Slab::default();
-        (0..10).for_each(|i| {
+    /// Like `clear`, but also releases the slab's memory. Versions are
+    /// carried over as in `shrink_to_fit`, so keys from before the call stay
+    /// invalid. Like `shrink_to_fit`, this only drops slots after the last
+    /// retired one: a retired slot can't be dropped without letting its stale
+    /// keys resolve again, so it and every slot before it stay allocated and
+    /// `capacity` may not reach 0.
+    pub fn clear_and_shrink(&mut self) {
+        self.clear();
+        self.shrink_to_fit();
+    }
+
+    /// Number of times the slab has been cleared.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Removes every entry for which `f` returns `false`, returning how many
+    /// were removed.
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let before = self.taken;
+        self.values.iter_mut().enumerate().for_each(|(i, v)| {
+            if v.is_occupied()
+                && !f(
+                    &K::init_with_generation(v.version, Idx::from_usize(i), self.generation),
+                    v.value.as_mut().unwrap(),
+                )
+            {
+                v.vacate();
+                if !v.retired() {
+                    self.free.push(Idx::from_usize(i));
+                }
+            }
+        });
+        self.taken = self.values.iter().filter(|v| v.is_occupied()).count();
+        before - self.taken
+    }
+
+    /// Like `retain`, but `f` can also keep an entry under a new version,
+    /// invalidating the entry's old keys without a remove and insert. An
+    /// entry whose version can't advance any further is removed instead.
+    /// Returns how many entries were removed.
+    pub fn retain_versioned<F: FnMut(K, &mut V) -> Retain>(&mut self, mut f: F) -> usize {
+        let mut removed = 0;
+        for i in 0..self.values.len() {
+            let slot = &mut self.values[i];
+            if slot.is_vacant() {
+                continue;
+            }
+            let key = K::init_with_generation(slot.version, Idx::from_usize(i), self.generation);
+            let bumped = match f(key, slot.value.as_mut().unwrap()) {
+                Retain::Keep => continue,
+                Retain::Remove => None,
+                Retain::KeepBumped => Idx::next_version(slot.version).and_then(Idx::next_version),
+            };
+            match bumped {
+                Some(version) => slot.version = version,
+                None => {
+                    self.release(Idx::from_usize(i));
+                    removed += 1;
+                }
+            }
+        }
+        removed
+    }
+
+    /// Passes every live value to `f` by move, storing the value it returns
+    /// back under the same key, or removing the entry if it returns `None`.
+    /// Returns how many entries were removed. If `f` panics, the entry it
+    /// was called with is removed.
+    pub fn retain_map<F: FnMut(&K, V) -> Option<V>>(&mut self, mut f: F) -> usize {
+        let mut removed = 0;
+        for i in 0..self.values.len() {
+            let slot = &self.values[i];
+            if slot.is_vacant() {
+                continue;
+            }
+            let version = slot.version;
+            let key = K::init_with_generation(version, Idx::from_usize(i), self.generation);
+            let value = self.release(Idx::from_usize(i)).unwrap();
+            match f(&key, value) {
+                Some(value) => {
+                    let slot = &mut self.values[i];
+                    if !slot.retired() {
+                        self.free.pop();
+                    }
+                    slot.version = version;
+                    slot.value = Some(value);
+                    self.taken += 1;
+                }
+                None => removed += 1,
+            }
+        }
+        removed
+    }
+
+    /// Calls `f` on every live entry in index order until it returns
+    /// `ControlFlow::Break`.
+    pub fn for_each_mut<F: FnMut(K, &mut V) -> ControlFlow<()>>(&mut self, mut f: F) {
+        let _ = self.iter_mut().try_for_each(|(k, v)| f(k, v));
+    }
+
+    /// Like `retain`, but only visits slots whose index lies in `range`,
+    /// leaving everything outside the window untouched. Sweeping the window
+    /// across calls spreads a full pass over several frames. Returns how many
+    /// entries were removed.
+    pub fn retain_range<F: FnMut(&K, &mut V) -> bool>(
+        &mut self,
+        range: impl core::ops::RangeBounds<Idx>,
+        mut f: F,
+    ) -> usize {
+        use core::ops::Bound;
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start.to_usize(),
+            Bound::Excluded(&start) => start.to_usize() + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.to_usize() + 1,
+            Bound::Excluded(&end) => end.to_usize(),
+            Bound::Unbounded => self.values.len(),
+        }
+        .min(self.values.len());
+        let before = self.taken;
+        for index in start..end {
+            let v = &mut self.values[index];
+            let key = K::init_with_generation(v.version, Idx::from_usize(index), self.generation);
+            if v.is_occupied() && !f(&key, v.value.as_mut().unwrap()) {
+                self.release(Idx::from_usize(index));
+            }
+        }
+        before - self.taken
+    }
+
+    /// Calls `f` for every live entry together with its component in `comp`,
+    /// inserting `U::default()` for entries that don't have one yet. Entries
+    /// `comp` refuses a component for, because their index is past its bound
+    /// or their slot holds a newer version, are skipped without calling `f`.
+    pub fn for_each_with_component<U: Default>(
+        &self,
+        comp: &mut AssociatedData<K, U, V, Idx>,
+        mut f: impl FnMut(K, &V, &mut U),
+    ) {
+        for (key, value) in self.iter() {
+            if let Some(component) = comp.try_get_or_insert_with(key.clone(), U::default) {
+                f(key, value, component);
+            }
+        }
+    }
+
+    /// Splits the slots into two disjoint mutable views, one over the indices
+    /// below `index` and one over the rest. `index` is clamped to the number
+    /// of slots.
+    pub fn split_at_index_mut(
+        &mut self,
+        index: Idx,
+    ) -> (SlabSliceMut<'_, V, Idx>, SlabSliceMut<'_, V, Idx>) {
+        let index = index.to_usize().min(self.values.len());
+        let (front, back) = self.values.split_at_mut(index);
+        (
+            SlabSliceMut {
+                slots: front,
+                offset: Idx::from_usize(0),
+            },
+            SlabSliceMut {
+                slots: back,
+                offset: Idx::from_usize(index),
+            },
+        )
+    }
+
+    /// Checks the slab's internal invariants, returning a description of the
+    /// first one that is violated.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.values.len() > Idx::MAX.to_usize() {
+            return Err("more slots than the index type can address");
+        }
+        let mut occupied = 0;
+        for slot in &self.values {
+            if slot.is_occupied() != slot.value.is_some() {
+                return Err("slot value does not match its version parity");
+            }
+            occupied += slot.is_occupied() as usize;
+        }
+        if occupied != self.taken {
+            return Err("taken does not match the number of occupied slots");
+        }
+        let mut seen = vec![false; self.values.len()];
+        for &index in &self.free {
+            match self.values.get(index.to_usize()) {
+                None => return Err("free list index out of bounds"),
+                Some(slot) if slot.is_occupied() => {
+                    return Err("free list contains an occupied slot")
+                }
+                Some(slot) if slot.retired() => return Err("free list contains a retired slot"),
+                Some(_) if seen[index.to_usize()] => return Err("free list contains a duplicate"),
+                Some(_) => seen[index.to_usize()] = true,
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with a mutable reference to the value under `key` and a
+    /// [`Spawner`] for inserting new entries in the meantime. The spawned
+    /// values are inserted after `f` returns, in order, and each ends up under
+    /// exactly the key `Spawner::spawn` returned for it. Returns `None`
+    /// without calling `f` if `key` is not live.
+    ///
+    /// This takes a closure rather than returning `(&mut V, Spawner)` with a
+    /// spawner that flushes on drop: flushing needs `&mut` access to the whole
+    /// slab, which would alias the `&mut V` still handed out, and pushing new
+    /// slots may reallocate the storage it points into. Scoping the borrow to
+    /// `f` lets the flush run only once that reference is gone.
+    pub fn get_mut_with_spawner<R>(
+        &mut self,
+        key: K,
+        f: impl FnOnce(&mut V, &mut Spawner<'_, K, V, Idx>) -> R,
+    ) -> Option<R> {
+        self.slot(&key)?;
+        let len = self.values.len();
+        let (before, rest) = self.values.split_at_mut(key.index().to_usize());
+        let (slot, after) = rest.split_first_mut().unwrap();
+        let mut spawner = Spawner {
+            free: &self.free,
+            before,
+            after,
+            len,
+            fresh_version: self.fresh_version,
+            generation: self.generation,
+            pending: vec![],
+            __phantom: core::marker::PhantomData,
+        };
+        let result = f(slot.value.as_mut().unwrap(), &mut spawner);
+        for value in spawner.pending {
+            self.insert(value);
+        }
+        Some(result)
+    }
+
+    /// Mutably borrows the value under `key` together with a [`SlabView`]
+    /// that reads every other live entry. The view returns `None` for `key`
+    /// itself. Returns `None` if `key` is not live.
+    pub fn split_at_key(&mut self, key: K) -> Option<(&mut V, SlabView<'_, K, V, Idx>)> {
+        self.slot(&key)?;
+        let (before, rest) = self.values.split_at_mut(key.index().to_usize());
+        let (slot, after) = rest.split_first_mut().unwrap();
+        let view = SlabView {
+            before,
+            after,
+            generation: self.generation,
+            __phantom: core::marker::PhantomData,
+        };
+        Some((slot.value.as_mut().unwrap(), view))
+    }
+
+    /// Merges each value of `data` into the slab entry with the same key using
+    /// `f`. Keys missing from either side are skipped.
+    pub fn apply_associated<U>(
+        &mut self,
+        data: &AssociatedData<K, U, V, Idx>,
+        mut f: impl FnMut(&mut V, &U),
+    ) {
+        for (key, associated) in data.iter() {
+            if let Some(value) = self.get_mut(key) {
+                f(value, associated);
+            }
+        }
+    }
+
+    /// Binary searches the slab's values with the comparator `f`.
+    ///
+    /// The slab must be gapless (every slot occupied) and its values sorted
+    /// by index according to `f`; if they aren't sorted the result is
+    /// meaningless. On a match returns the key of the found entry, otherwise
+    /// the index at which the value would have to be inserted to keep the
+    /// order. A slab with vacant slots isn't searched: the result is
+    /// `Err(n)`, where `n` is the number of slots, and `f` isn't called.
+    pub fn binary_search_by<F>(&self, mut f: F) -> Result<K, usize>
+    where
+        F: FnMut(&V) -> core::cmp::Ordering,
+    {
+        if self.taken != self.values.len() {
+            return Err(self.values.len());
+        }
+        self.values
+            .binary_search_by(|slot| f(slot.value.as_ref().unwrap()))
+            .map(|i| {
+                K::init_with_generation(self.values[i].version, Idx::from_usize(i), self.generation)
+            })
+    }
+
+    /// Removes every entry whose projected key was already produced by an
+    /// entry at a lower index, returning how many entries were removed.
+    ///
+    /// Only available with the `std` feature, since it tracks the keys seen
+    /// so far in a `HashSet`.
+    #[cfg(feature = "std")]
+    pub fn dedup_by_key<B, F>(&mut self, mut key_fn: F) -> usize
+    where
+        B: Eq + core::hash::Hash,
+        F: FnMut(&V) -> B,
+    {
+        let mut seen = HashSet::new();
+        let mut removed = 0;
+        for i in 0..self.values.len() {
+            let slot = &self.values[i];
+            if slot.is_occupied() && !seen.insert(key_fn(slot.value.as_ref().unwrap())) {
+                self.release(Idx::from_usize(i));
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Packs slot occupancy into a bitmap: bit `i % 64` of word `i / 64` is set
+    /// iff slot `i` is occupied.
+    pub fn occupancy_bitmap(&self) -> Vec<u64> {
+        let mut bitmap = vec![0u64; self.values.len().div_ceil(64)];
+        for (i, slot) in self.values.iter().enumerate() {
+            if slot.is_occupied() {
+                bitmap[i / 64] |= 1 << (i % 64);
+            }
+        }
+        bitmap
+    }
+
+    /// Removes every occupied slot whose bit is cleared in `bitmap`. Slots
+    /// past the end of the bitmap count as cleared.
+    pub fn apply_removals_from_bitmap(&mut self, bitmap: &[u64]) {
+        for i in 0..self.values.len() {
+            let set = bitmap
+                .get(i / 64)
+                .is_some_and(|word| word & (1 << (i % 64)) != 0);
+            if self.values[i].is_occupied() && !set {
+                self.release(Idx::from_usize(i));
+            }
+        }
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        SlabStats {
+            len: self.len(),
+            slots: self.values.len(),
+            capacity: self.capacity(),
+            vacant: self.values.len() - self.len(),
+            free_len: self.free.len(),
+            highest_occupied: self.values.iter().rposition(|v| v.is_occupied()),
+            inserted: self.counters.inserted,
+            recycled: self.counters.recycled,
+            peak_len: self.counters.peak_len,
+        }
+    }
+
+    /// Computes the churn since `since`, an earlier snapshot of this slab.
+    pub fn stats_delta(&self, since: &SlabStats) -> StatsDelta {
+        let inserts = self.counters.inserted - since.inserted;
+        StatsDelta {
+            inserts,
+            removes: since.len as u64 + inserts - self.len() as u64,
+            recycled: self.counters.recycled - since.recycled,
+            growth: self.values.len() as isize - since.slots as isize,
+        }
+    }
+}
+
+/// What [`Slab::retain_versioned`] does with an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Retain {
+    Keep,
+    Remove,
+    /// Keep the value but advance the slot's version, so existing keys for
+    /// the entry stop resolving.
+    KeepBumped,
+}
+
+/// A mutable view over a contiguous range of a slab's slots, see
+/// [`Slab::split_at_index_mut`]. Values can be read and mutated, but entries
+/// can't be inserted or removed through it.
+pub struct SlabSliceMut<'a, V, Idx: SlotInt = u32> {
+    slots: &'a mut [Slot<V, Idx>],
+    offset: Idx,
+}
+
+impl<'a, V, Idx: SlotInt> SlabSliceMut<'a, V, Idx> {
+    /// Index in the slab of the first slot in this view.
+    pub fn offset(&self) -> Idx {
+        self.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|v| v.is_occupied()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(|v| v.is_vacant())
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.slots
+            .iter()
+            .filter(|v| v.is_occupied())
+            .filter_map(|v| v.value.as_ref())
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.slots
+            .iter_mut()
+            .filter(|v| v.is_occupied())
+            .filter_map(|v| v.value.as_mut())
+    }
+}
+
+/// Buffers inserts made while a slab entry is mutably borrowed, see
+/// [`Slab::get_mut_with_spawner`].
+pub struct Spawner<'a, K, V, Idx: SlotInt = u32> {
+    free: &'a [Idx],
+    before: &'a [Slot<V, Idx>],
+    after: &'a [Slot<V, Idx>],
+    len: usize,
+    fresh_version: Idx::Version,
+    generation: u64,
+    pending: Vec<V>,
+    __phantom: core::marker::PhantomData<K>,
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> Spawner<'a, K, V, Idx> {
+    /// Queues `value` for insertion and returns the key it will be stored
+    /// under once the spawner is flushed.
+    pub fn spawn(&mut self, value: V) -> K {
+        let n = self.pending.len();
+        let key = if n < self.free.len() {
+            let index = self.free[self.free.len() - 1 - n];
+            let split = self.before.len();
+            let slot = match index.to_usize().checked_sub(split + 1) {
+                Some(i) => &self.after[i],
+                None => &self.before[index.to_usize()],
+            };
+            K::init_with_generation(
+                Idx::next_version(slot.version).unwrap(),
+                index,
+                self.generation,
+            )
+        } else {
+            let index = self.len + n - self.free.len();
+            K::init_with_generation(self.fresh_version, Idx::from_usize(index), self.generation)
+        };
+        self.pending.push(value);
+        key
+    }
+
+    /// Number of inserts queued so far.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Read-only view over every slot of a slab except one, see
+/// [`Slab::split_at_key`].
+pub struct SlabView<'a, K, V, Idx: SlotInt = u32> {
+    before: &'a [Slot<V, Idx>],
+    after: &'a [Slot<V, Idx>],
+    generation: u64,
+    __phantom: core::marker::PhantomData<K>,
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> SlabView<'a, K, V, Idx> {
+    /// Returns the value under `key`, or `None` if it is not live or is the
+    /// key that was split out.
+    pub fn get(&self, key: K) -> Option<&'a V> {
+        if !key.validate_generation(self.generation) {
+            return None;
+        }
+        let index = key.index().to_usize();
+        let split = self.before.len();
+        let slot = match index.checked_sub(split + 1) {
+            Some(i) => self.after.get(i)?,
+            None => self.before.get(index)?,
+        };
+        slot.value
+            .as_ref()
+            .filter(|_| slot.is_occupied() && key.validate(slot.version))
+    }
+
+    pub fn contains_key(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+/// Cursor over a slab's live entries, see [`Slab::cursor_mut`].
+pub struct CursorMut<'a, K: Key<V, Idx> + Clone, V, Idx: SlotInt = u32> {
+    slab: &'a mut Slab<K, V, Idx>,
+    index: usize,
+}
+
+impl<'a, K: Key<V, Idx> + Clone, V, Idx: SlotInt> CursorMut<'a, K, V, Idx> {
+    fn seek(&mut self, from: usize) {
+        self.index = from;
+        while self
+            .slab
+            .values
+            .get(self.index)
+            .is_some_and(|slot| slot.is_vacant())
+        {
+            self.index += 1;
+        }
+    }
+
+    /// Returns the entry under the cursor, or `None` if the cursor is past
+    /// the end or the entry was just removed.
+    pub fn current(&mut self) -> Option<(K, &mut V)> {
+        let slot = self.slab.values.get_mut(self.index)?;
+        let key = K::init_with_generation(
+            slot.version,
+            Idx::from_usize(self.index),
+            self.slab.generation,
+        );
+        Some((key, slot.value.as_mut()?))
+    }
+
+    /// Removes the entry under the cursor. The cursor stays in place, so
+    /// `advance` moves on to the next live entry.
+    pub fn remove_current(&mut self) -> Option<V> {
+        if self.slab.values.get(self.index)?.is_vacant() {
+            return None;
+        }
+        self.slab.release(Idx::from_usize(self.index))
+    }
+
+    /// Moves the cursor to the next live entry.
+    pub fn advance(&mut self) {
+        if self.index < self.slab.values.len() {
+            self.seek(self.index + 1);
+        }
+    }
+}
+
+/// Draining iterator returned by [`Slab::drain`].
+pub struct Drain<'a, K: Key<V, Idx> + Clone, V, Idx: SlotInt = u32> {
+    slab: &'a mut Slab<K, V, Idx>,
+    index: usize,
+}
+
+impl<'a, K: Key<V, Idx> + Clone, V, Idx: SlotInt> Iterator for Drain<'a, K, V, Idx> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.slab.values.len() {
+            let index = Idx::from_usize(self.index);
+            self.index += 1;
+            let slot = &self.slab.values[index.to_usize()];
+            if slot.is_occupied() {
+                let key = K::init_with_generation(slot.version, index, self.slab.generation);
+                return self.slab.release(index).map(|value| (key, value));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.slab.len(), Some(self.slab.len()))
+    }
+}
+
+impl<'a, K: Key<V, Idx> + Clone, V, Idx: SlotInt> ExactSizeIterator for Drain<'a, K, V, Idx> {}
+
+impl<'a, K: Key<V, Idx> + Clone, V, Idx: SlotInt> FusedIterator for Drain<'a, K, V, Idx> {}
+
+impl<'a, K: Key<V, Idx> + Clone, V, Idx: SlotInt> Drop for Drain<'a, K, V, Idx> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+/// Iterator returned by [`Slab::iter`].
+pub struct Iter<'a, K, V, Idx: SlotInt = u32> {
+    slots: Enumerate<core::slice::Iter<'a, Slot<V, Idx>>>,
+    remaining: usize,
+    generation: u64,
+    __phantom: core::marker::PhantomData<K>,
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> Iterator for Iter<'a, K, V, Idx> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (i, slot) = self.slots.find(|(_, v)| v.is_occupied())?;
+        self.remaining -= 1;
+        Some((
+            K::init_with_generation(slot.version, Idx::from_usize(i), self.generation),
+            slot.value.as_ref().unwrap(),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> DoubleEndedIterator for Iter<'a, K, V, Idx> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (i, slot) = self.slots.rfind(|(_, v)| v.is_occupied())?;
+        self.remaining -= 1;
+        Some((
+            K::init_with_generation(slot.version, Idx::from_usize(i), self.generation),
+            slot.value.as_ref().unwrap(),
+        ))
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> ExactSizeIterator for Iter<'a, K, V, Idx> {}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> FusedIterator for Iter<'a, K, V, Idx> {}
+
+/// Iterator returned by [`Slab::iter_mut`].
+pub struct IterMut<'a, K, V, Idx: SlotInt = u32> {
+    slots: Enumerate<core::slice::IterMut<'a, Slot<V, Idx>>>,
+    remaining: usize,
+    generation: u64,
+    __phantom: core::marker::PhantomData<K>,
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> Iterator for IterMut<'a, K, V, Idx> {
+    type Item = (K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (i, slot) = self.slots.find(|(_, v)| v.is_occupied())?;
+        self.remaining -= 1;
+        Some((
+            K::init_with_generation(slot.version, Idx::from_usize(i), self.generation),
+            slot.value.as_mut().unwrap(),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> DoubleEndedIterator for IterMut<'a, K, V, Idx> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (i, slot) = self.slots.rfind(|(_, v)| v.is_occupied())?;
+        self.remaining -= 1;
+        Some((
+            K::init_with_generation(slot.version, Idx::from_usize(i), self.generation),
+            slot.value.as_mut().unwrap(),
+        ))
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> ExactSizeIterator for IterMut<'a, K, V, Idx> {}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> FusedIterator for IterMut<'a, K, V, Idx> {}
+
+/// Consuming iterator returned by [`Slab::into_iter_with_keys`].
+pub struct IntoIter<K, V, Idx: SlotInt = u32> {
+    slots: Enumerate<alloc::vec::IntoIter<Slot<V, Idx>>>,
+    remaining: usize,
+    generation: u64,
+    __phantom: core::marker::PhantomData<K>,
+}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> Iterator for IntoIter<K, V, Idx> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (i, slot) = self.slots.find(|(_, v)| v.is_occupied())?;
+        self.remaining -= 1;
+        Some((
+            K::init_with_generation(slot.version, Idx::from_usize(i), self.generation),
+            slot.value.unwrap(),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> DoubleEndedIterator for IntoIter<K, V, Idx> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (i, slot) = self.slots.rfind(|(_, v)| v.is_occupied())?;
+        self.remaining -= 1;
+        Some((
+            K::init_with_generation(slot.version, Idx::from_usize(i), self.generation),
+            slot.value.unwrap(),
+        ))
+    }
+}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> ExactSizeIterator for IntoIter<K, V, Idx> {}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> FusedIterator for IntoIter<K, V, Idx> {}
+
+/// Consuming iterator returned by [`Slab::into_iter`].
+pub struct IntoValues<K, V, Idx: SlotInt = u32>(IntoIter<K, V, Idx>);
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> Iterator for IntoValues<K, V, Idx> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.0.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> DoubleEndedIterator for IntoValues<K, V, Idx> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> ExactSizeIterator for IntoValues<K, V, Idx> {}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> FusedIterator for IntoValues<K, V, Idx> {}
+
+/// Iterator returned by [`Slab::keys`].
+pub struct Keys<'a, K, V, Idx: SlotInt = u32>(Iter<'a, K, V, Idx>);
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> Iterator for Keys<'a, K, V, Idx> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.0.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> DoubleEndedIterator for Keys<'a, K, V, Idx> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> ExactSizeIterator for Keys<'a, K, V, Idx> {}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> FusedIterator for Keys<'a, K, V, Idx> {}
+
+/// Iterator returned by [`Slab::values`].
+pub struct Values<'a, K, V, Idx: SlotInt = u32>(Iter<'a, K, V, Idx>);
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> Iterator for Values<'a, K, V, Idx> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        self.0.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> DoubleEndedIterator for Values<'a, K, V, Idx> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> ExactSizeIterator for Values<'a, K, V, Idx> {}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> FusedIterator for Values<'a, K, V, Idx> {}
+
+/// Iterator returned by [`Slab::values_mut`].
+pub struct ValuesMut<'a, K, V, Idx: SlotInt = u32>(IterMut<'a, K, V, Idx>);
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> Iterator for ValuesMut<'a, K, V, Idx> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<&'a mut V> {
+        self.0.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> DoubleEndedIterator for ValuesMut<'a, K, V, Idx> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> ExactSizeIterator for ValuesMut<'a, K, V, Idx> {}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> FusedIterator for ValuesMut<'a, K, V, Idx> {}
+
+/// A single slab operation for [`Slab::replay`].
+#[cfg(any(test, feature = "testing"))]
+#[derive(Clone, Debug)]
+pub enum Op<V> {
+    Insert(V),
+    /// Removes the entry under the nth key handed out by an `Insert`.
+    Remove(usize),
+    Clear,
+    Retain(fn(&V) -> bool),
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl<K: Key<V, Idx> + Clone, V: Clone, Idx: SlotInt> Slab<K, V, Idx> {
+    /// Applies `ops` in order to an empty slab and returns the result, so a
+    /// failing sequence from a fuzzer or bug report can be reproduced.
+    /// `Remove` of a key that was never handed out is ignored.
+    pub fn replay(ops: &[Op<V>]) -> Self {
+        let mut slab = Self::new();
+        let mut keys = vec![];
+        for op in ops {
+            match op {
+                Op::Insert(value) => keys.push(slab.insert(value.clone())),
+                Op::Remove(n) => {
+                    if let Some(key) = keys.get(*n) {
+                        slab.remove(key.clone());
+                    }
+                }
+                Op::Clear => slab.clear(),
+                Op::Retain(f) => {
+                    slab.retain(|_, v| f(v));
+                }
+            }
+        }
+        slab
+    }
+}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> IntoIterator for Slab<K, V, Idx> {
+    type Item = V;
+
+    type IntoIter = IntoValues<K, V, Idx>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoValues(IntoIter {
+            remaining: self.taken,
+            generation: self.generation,
+            slots: self.values.into_iter().enumerate(),
+            __phantom: core::marker::PhantomData,
+        })
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> IntoIterator for &'a Slab<K, V, Idx> {
+    type Item = (K, &'a V);
+
+    type IntoIter = Iter<'a, K, V, Idx>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Iter {
+            slots: self.values.iter().enumerate(),
+            remaining: self.taken,
+            generation: self.generation,
+            __phantom: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<'a, K: Key<V, Idx>, V, Idx: SlotInt> IntoIterator for &'a mut Slab<K, V, Idx> {
+    type Item = (K, &'a mut V);
+
+    type IntoIter = IterMut<'a, K, V, Idx>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IterMut {
+            remaining: self.taken,
+            generation: self.generation,
+            slots: self.values.iter_mut().enumerate(),
+            __phantom: core::marker::PhantomData,
+        }
+    }
+}
+
+/// Collects values into a new slab. The keys are discarded; use `extend_keys`
+/// on an existing slab to keep them.
+impl<K: Key<V, Idx> + Clone, V, Idx: SlotInt> FromIterator<V> for Slab<K, V, Idx> {
+    fn from_iter<I: IntoIterator<Item = V>>(iter: I) -> Self {
+        let mut slab = Self::new();
+        slab.extend(iter);
+        slab
+    }
+}
+
+/// Inserts every value, discarding the keys. Capacity for the iterator's
+/// lower size hint is reserved up front.
+impl<K: Key<V, Idx> + Clone, V, Idx: SlotInt> Extend<V> for Slab<K, V, Idx> {
+    fn extend<I: IntoIterator<Item = V>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        iter.for_each(|value| {
+            self.insert(value);
+        });
+    }
+}
+
+/// Two slabs are equal when they hold equal values under the same keys, that
+/// is, at the same indices with the same versions. Vacant slots are ignored.
+impl<K: Key<V, Idx> + Clone, V: PartialEq, Idx: SlotInt> PartialEq for Slab<K, V, Idx> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().zip(other.iter()).all(|((a, x), (b, y))| {
+                a.index() == b.index() && a.version() == b.version() && x == y
+            })
+    }
+}
+
+impl<K: Key<V, Idx> + Clone, V: Eq, Idx: SlotInt> Eq for Slab<K, V, Idx> {}
+
+impl<K: Key<V, Idx> + Clone + Debug, V, Idx: SlotInt> Index<K> for Slab<K, V, Idx> {
+    type Output = V;
+
+    /// Panics if `key` is out of range or stale; use `get` to check instead.
+    fn index(&self, key: K) -> &V {
+        match self.get(key.clone()) {
+            Some(value) => value,
+            None => panic!("invalid or stale key: {key:?}"),
+        }
+    }
+}
+
+impl<K: Key<V, Idx> + Clone + Debug, V, Idx: SlotInt> IndexMut<K> for Slab<K, V, Idx> {
+    /// Panics if `key` is out of range or stale; use `get_mut` to check instead.
+    fn index_mut(&mut self, key: K) -> &mut V {
+        match self.get_mut(key.clone()) {
+            Some(value) => value,
+            None => panic!("invalid or stale key: {key:?}"),
+        }
+    }
+}
+
+/// `{:?}` prints a summary of the slab's size; `{:#?}` lists every live entry.
+impl<K: Key<V, Idx> + Clone, V: Debug, Idx: SlotInt> Debug for Slab<K, V, Idx> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if !f.alternate() {
+            return f
+                .debug_struct("Slab")
+                .field("len", &self.len())
+                .field("capacity", &self.capacity())
+                .field("free", &self.free.len())
+                .finish();
+        }
+        let mut dbg = f.debug_struct(&format!(
+            "Slab<{}, {}>",
+            core::any::type_name::<K>(),
+            core::any::type_name::<V>()
+        ));
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_occupied())
+            .for_each(|(i, v)| {
+                dbg.field(&format!("{}v{}", i, v.version), v.value.as_ref().unwrap());
+            });
+        dbg.finish()
+    }
+}
+
+/// A slab that can no longer be modified, see [`Slab::freeze`]. It derefs to
+/// the slab for every read-only method and is `Sync` when the values are.
+pub struct FrozenSlab<K: Key<V, Idx>, V, Idx: SlotInt = u32> {
+    slab: Slab<K, V, Idx>,
+}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> FrozenSlab<K, V, Idx> {
+    /// Returns the slab, making it mutable again.
+    pub fn thaw(self) -> Slab<K, V, Idx> {
+        self.slab
+    }
+}
+
+impl<K: Key<V, Idx>, V, Idx: SlotInt> core::ops::Deref for FrozenSlab<K, V, Idx> {
+    type Target = Slab<K, V, Idx>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.slab
+    }
+}
+
+impl<K: Key<V, Idx> + Clone, V: Debug, Idx: SlotInt> Debug for FrozenSlab<K, V, Idx> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.slab.fmt(f)
+    }
+}
+
+/// What [`AssociatedData::insert_with_policy`] does when the slot already
+/// holds a value under a different version of the key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Overwrite values from older versions but keep newer ones, as `insert`
+    /// does.
+    RefuseNewer,
+    /// Overwrite the value whatever its version.
+    AlwaysOverwrite,
+    /// Keep the existing value whatever its version.
+    RefuseAny,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertOutcome<V> {
+    /// The slot was vacant.
+    Inserted,
+    /// The slot held a value for the same version, which is returned.
+    Replaced(V),
+    /// The slot held a value for another version, which is returned.
+    Overwrote(V),
+    /// Nothing was stored.
+    Rejected(Rejection),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The slot holds a value for a newer version.
+    NewerVersion,
+    /// The slot holds a value for an older version.
+    OlderVersion,
+    /// The key's index is past the map's bound.
+    OutOfBounds,
+}
+
+/// Like [`Slab`], `Send` and `Sync` when its values are.
+pub struct AssociatedData<K: Key<N, Idx>, V, N, Idx: SlotInt = u32> {
+    items: Vec<Slot<V, Idx>>,
+    taken: usize,
+    /// Highest index a key may have; see `AssociatedData::with_bound`.
+    bound: Idx,
+    __phantom: core::marker::PhantomData<fn() -> (K, N)>,
+}
+
+impl<K: Key<N, Idx>, V: Clone, N, Idx: SlotInt> Clone for AssociatedData<K, V, N, Idx> {
+    fn clone(&self) -> Self {
+        Self {
+            items: self.items.clone(),
+            taken: self.taken,
+            bound: self.bound,
+            __phantom: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<K: Key<N, Idx>, V, N, Idx: SlotInt> Default for AssociatedData<K, V, N, Idx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Pairs each value with a preexisting key, following the same rules as
+/// `AssociatedData::insert` when two keys share an index.
+impl<K: Key<N, Idx>, V, N, Idx: SlotInt> FromIterator<(K, V)> for AssociatedData<K, V, N, Idx> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut data = Self::new();
+        data.extend(iter);
+        data
+    }
+}
+
+impl<K: Key<N, Idx>, V, N, Idx: SlotInt> Extend<(K, V)> for AssociatedData<K, V, N, Idx> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.insert_many_with_keys(iter);
+    }
+}
+
+impl<K: Key<N, Idx>, V: Debug, N, Idx: SlotInt> Debug for AssociatedData<K, V, N, Idx> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut dbg = f.debug_struct(&format!(
+            "AssociatedData<{}, {}>",
+            core::any::type_name::<K>(),
+            core::any::type_name::<V>()
+        ));
+        self.items
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_occupied())
+            .for_each(|(i, v)| {
+                dbg.field(&format!("{}v{}", i, v.version), v.value.as_ref().unwrap());
+            });
+        dbg.finish()
+    }
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> IntoIterator for &'a AssociatedData<K, V, N, Idx> {
+    type Item = (K, &'a V);
+
+    type IntoIter = AssociatedIter<'a, K, V, N, Idx>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> IntoIterator for &'a mut AssociatedData<K, V, N, Idx> {
+    type Item = (K, &'a mut V);
+
+    type IntoIter = AssociatedIterMut<'a, K, V, N, Idx>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// Iterator returned by [`AssociatedData::iter`].
+pub struct AssociatedIter<'a, K, V, N, Idx: SlotInt = u32> {
+    slots: Enumerate<core::slice::Iter<'a, Slot<V, Idx>>>,
+    remaining: usize,
+    __phantom: core::marker::PhantomData<fn() -> (K, N)>,
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> Iterator for AssociatedIter<'a, K, V, N, Idx> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (i, slot) = self.slots.find(|(_, v)| v.is_occupied())?;
+        self.remaining -= 1;
+        Some((
+            K::init(slot.version, Idx::from_usize(i)),
+            slot.value.as_ref().unwrap(),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> ExactSizeIterator
+    for AssociatedIter<'a, K, V, N, Idx>
+{
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> FusedIterator for AssociatedIter<'a, K, V, N, Idx> {}
+
+/// Iterator returned by [`AssociatedData::iter_mut`].
+pub struct AssociatedIterMut<'a, K, V, N, Idx: SlotInt = u32> {
+    slots: Enumerate<core::slice::IterMut<'a, Slot<V, Idx>>>,
+    remaining: usize,
+    __phantom: core::marker::PhantomData<fn() -> (K, N)>,
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> Iterator for AssociatedIterMut<'a, K, V, N, Idx> {
+    type Item = (K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (i, slot) = self.slots.find(|(_, v)| v.is_occupied())?;
+        self.remaining -= 1;
+        Some((
+            K::init(slot.version, Idx::from_usize(i)),
+            slot.value.as_mut().unwrap(),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> ExactSizeIterator
+    for AssociatedIterMut<'a, K, V, N, Idx>
+{
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> FusedIterator for AssociatedIterMut<'a, K, V, N, Idx> {}
+
+/// A view into a single slot of [`AssociatedData`], see
+/// [`AssociatedData::entry`].
+pub enum Entry<'a, K: Key<N, Idx>, V, N, Idx: SlotInt = u32> {
+    Occupied(OccupiedEntry<'a, K, V, N, Idx>),
+    Vacant(VacantEntry<'a, K, V, N, Idx>),
+    /// The key can't be stored, because its slot holds a value for a newer
+    /// version or its index is past the map's bound.
+    Rejected(RejectedEntry<K>),
+}
+
+/// An entry holding a value for exactly this key's version.
+pub struct OccupiedEntry<'a, K: Key<N, Idx>, V, N, Idx: SlotInt = u32> {
+    data: &'a mut AssociatedData<K, V, N, Idx>,
+    key: K,
+}
+
+/// An entry that is empty or holds a value for an older version of the key.
+pub struct VacantEntry<'a, K: Key<N, Idx>, V, N, Idx: SlotInt = u32> {
+    data: &'a mut AssociatedData<K, V, N, Idx>,
+    key: K,
+}
+
+/// An entry for a key the map refuses, see [`Entry::Rejected`].
+pub struct RejectedEntry<K> {
+    key: K,
+    reason: Rejection,
+}
+
+impl<K> RejectedEntry<K> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Either [`Rejection::NewerVersion`] or [`Rejection::OutOfBounds`].
+    pub fn reason(&self) -> Rejection {
+        self.reason
+    }
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> Entry<'a, K, V, N, Idx> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Rejected(entry) => entry.key(),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the entry is [`Entry::Rejected`].
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the entry is [`Entry::Rejected`], without calling `f`.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(f()),
+            Entry::Rejected(entry) => panic!("entry was rejected: {:?}", entry.reason),
+        }
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> OccupiedEntry<'a, K, V, N, Idx> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn get(&self) -> &V {
+        self.data.items[self.key.index().to_usize()]
+            .value
+            .as_ref()
+            .unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.data.items[self.key.index().to_usize()]
+            .value
+            .as_mut()
+            .unwrap()
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.data.items[self.key.index().to_usize()]
+            .value
+            .as_mut()
+            .unwrap()
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        core::mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        self.data.taken -= 1;
+        self.data.items[self.key.index().to_usize()]
+            .vacate()
+            .unwrap()
+    }
+}
+
+impl<'a, K: Key<N, Idx>, V, N, Idx: SlotInt> VacantEntry<'a, K, V, N, Idx> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V {
+        let data = self.data;
+        let index = self.key.index().to_usize();
+        if index >= data.items.len() {
+            data.items.resize_with(index + 1, Slot::new);
+        }
+        let slot = &mut data.items[index];
+        if slot.is_vacant() {
+            data.taken += 1;
+        }
+        slot.version = self.key.version();
+        slot.value.insert(value)
+    }
+}
+
+impl<K: Key<N, Idx>, V, N, Idx: SlotInt> AssociatedData<K, V, N, Idx> {
+    pub fn new() -> Self {
+        Self {
+            items: vec![],
+            taken: 0,
+            bound: Idx::MAX,
+            __phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Creates an empty map with room for keys with indices below
+    /// `capacity`, typically the paired slab's capacity. The storage is
+    /// allocated but not filled, so `len` and iteration are unaffected until
+    /// values are inserted.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            items: Vec::with_capacity(capacity),
+            ..Self::new()
+        }
+    }
+
+    /// Creates an empty map that treats keys with an index above
+    /// `max_index` as invalid: inserting them does nothing. By default any
+    /// index is accepted and the backing storage grows to fit it, so a key
+    /// from a much larger slab can trigger a huge allocation.
+    pub fn with_bound(max_index: Idx) -> Self {
+        Self {
+            bound: max_index,
+            ..Self::new()
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.insert_with_policy(key, value, ConflictPolicy::RefuseNewer) {
+            InsertOutcome::Replaced(old) => Some(old),
+            _ => None,
+        }
+    }
+
+    /// Like `insert`, but `policy` decides what happens when the slot holds
+    /// a value under a different version of the key.
+    pub fn insert_with_policy(
+        &mut self,
+        key: K,
+        value: V,
+        policy: ConflictPolicy,
+    ) -> InsertOutcome<V> {
+        let data = key.data();
+        let index = data.index.to_usize();
+        if data.index > self.bound {
+            return InsertOutcome::Rejected(Rejection::OutOfBounds);
+        }
+        self.reserve(data.index);
+        let slot = &mut self.items[index];
+        if slot.is_vacant() {
+            self.taken += 1;
+            slot.version = data.version;
+            slot.value = Some(value);
+            return InsertOutcome::Inserted;
+        }
+        if slot.same_version(&data.version) {
+            return InsertOutcome::Replaced(slot.swap(value).unwrap());
+        }
+        let newer = slot.newer_than(&data.version);
+        match policy {
+            ConflictPolicy::RefuseNewer if newer => {
+                return InsertOutcome::Rejected(Rejection::NewerVersion)
+            }
+            ConflictPolicy::RefuseAny => {
+                return InsertOutcome::Rejected(if newer {
+                    Rejection::NewerVersion
+                } else {
+                    Rejection::OlderVersion
+                })
+            }
+            _ => {}
+        }
+        slot.version = data.version;
+        InsertOutcome::Overwrote(slot.swap(value).unwrap())
+    }
+
+    /// Inserts every pair following the rules of `insert`, growing the
+    /// backing storage once up front to fit the highest index.
+    pub fn insert_many_with_keys<I: IntoIterator<Item = (K, V)>>(&mut self, pairs: I) {
+        let pairs: Vec<_> = pairs.into_iter().collect();
+        if let Some(max) = pairs.iter().map(|(key, _)| key.index()).max() {
+            self.reserve(max);
+        }
+        for (key, value) in pairs {
+            self.insert(key, value);
+        }
+    }
+
+    /// Allocates vacant slots for every index up to and including
+    /// `up_to_index`, so later inserts below it don't grow the backing
+    /// storage. Existing slots are left untouched. Never reserves past the
+    /// map's bound.
+    pub fn reserve(&mut self, up_to_index: Idx) {
+        let len = up_to_index.min(self.bound).to_usize() + 1;
+        if len > self.items.len() {
+            self.items.resize_with(len, Slot::new);
+        }
+    }
+
+    /// Drops the vacant slots at the end of the map and releases unused
+    /// memory. A vacant slot accepts any version, so this doesn't change
+    /// which inserts succeed.
+    pub fn shrink_to_fit(&mut self) {
+        let end = self
+            .items
+            .iter()
+            .rposition(|slot| slot.is_occupied())
+            .map_or(0, |i| i + 1);
+        self.items.truncate(end);
+        self.items.shrink_to_fit();
+    }
+
+    /// Gets the entry for `key`, following the same rules as `insert`: a
+    /// slot holding an older version counts as vacant, and the entry is
+    /// [`Entry::Rejected`] if the slot holds a value for a newer version of
+    /// the key or the key is past the map's bound.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, N, Idx> {
+        let data = key.data();
+        let reason = if data.index > self.bound {
+            Rejection::OutOfBounds
+        } else {
+            match self.items.get(data.index.to_usize()) {
+                Some(slot) if slot.is_occupied() && slot.newer_than(&data.version) => {
+                    Rejection::NewerVersion
+                }
+                Some(slot) if slot.is_occupied() && slot.same_version(&data.version) => {
+                    return Entry::Occupied(OccupiedEntry { data: self, key });
+                }
+                _ => return Entry::Vacant(VacantEntry { data: self, key }),
+            }
+        };
+        Entry::Rejected(RejectedEntry { key, reason })
+    }
+
+    /// Returns the value for `key`, inserting the result of `f` first if
+    /// there is none. A value stored under an older version is replaced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slot holds a value for a newer version of the key, or if
+    /// the key is past the map's bound; see `try_get_or_insert_with`.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        self.entry(key).or_insert_with(f)
+    }
+
+    /// Like `get_or_insert_with`, but returns `None` without calling `f`
+    /// where that would panic.
+    pub fn try_get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> Option<&mut V> {
+        match self.entry(key) {
+            Entry::Rejected(_) => None,
+            entry => Some(entry.or_insert_with(f)),
+        }
+    }
+
+    /// Stores `default` under `key` if there is no value for it yet, or runs
+    /// `update` on the existing one. Like `insert`, a value stored under an
+    /// older version is replaced, and nothing happens if the slot holds a
+    /// newer version or the key is past the map's bound.
+    pub fn insert_or_update<F: FnOnce(&mut V)>(&mut self, key: K, default: V, update: F) {
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => update(entry.get_mut()),
+            Entry::Vacant(entry) => {
+                entry.insert(default);
+            }
+            Entry::Rejected(_) => {}
+        }
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let data = key.data();
+        let index = data.index.to_usize();
+        if index >= self.items.len() {
+            return None;
+        }
+        let slot = &mut self.items[index];
+        if slot.is_occupied() && slot.same_version(&data.version) {
+            self.taken -= 1;
+            return slot.vacate();
+        }
+        None
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let data = key.data();
+        let index = data.index.to_usize();
+        if index >= self.items.len() {
+            return None;
+        }
+        let slot = &self.items[index];
+        if slot.is_occupied() && slot.same_version(&data.version) {
+            return slot.value.as_ref();
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: K) -> bool {
+        let data = key.data();
+        self.items
+            .get(data.index.to_usize())
+            .is_some_and(|slot| slot.is_occupied() && slot.same_version(&data.version))
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let data = key.data();
+        let index = data.index.to_usize();
+        if index >= self.items.len() {
+            return None;
+        }
+        let slot = &mut self.items[index];
+        if slot.is_occupied() && slot.same_version(&data.version) {
+            return slot.value.as_mut();
+        }
+        None
+    }
+
+    pub fn iter(&self) -> AssociatedIter<'_, K, V, N, Idx> {
+        AssociatedIter {
+            slots: self.items.iter().enumerate(),
+            remaining: self.taken,
+            __phantom: core::marker::PhantomData,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> AssociatedIterMut<'_, K, V, N, Idx> {
+        AssociatedIterMut {
+            remaining: self.taken,
+            slots: self.items.iter_mut().enumerate(),
+            __phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Iterates the keys that are live both here and in `slab`, yielding
+    /// each with its associated value and its slab value.
+    pub fn join<'a>(&'a self, slab: &'a Slab<K, N, Idx>) -> impl Iterator<Item = (K, &'a V, &'a N)>
+    where
+        K: Clone,
+    {
+        self.iter()
+            .filter_map(|(key, value)| Some((key.clone(), value, slab.get(key)?)))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        self.items
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_occupied())
+            .map(|(i, v)| K::init(v.version, Idx::from_usize(i)))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.items
+            .iter()
+            .filter(|v| v.is_occupied())
+            .filter_map(|v| v.value.as_ref())
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.items
+            .iter_mut()
+            .filter(|v| v.is_occupied())
+            .filter_map(|v| v.value.as_mut())
+    }
+
+    pub fn len(&self) -> usize {
+        self.taken
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taken == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.items.iter_mut().for_each(|v| {
+            v.vacate();
+        });
+        self.taken = 0;
+    }
+
+    /// Removes every entry for which `f` returns `false`, returning how many
+    /// were removed.
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let before = self.taken;
+        self.items.iter_mut().enumerate().for_each(|(i, v)| {
+            if v.is_occupied()
+                && !f(
+                    &K::init(v.version, Idx::from_usize(i)),
+                    v.value.as_mut().unwrap(),
+                )
+            {
+                v.vacate();
+            }
+        });
+        self.taken = self.items.iter().filter(|v| v.is_occupied()).count();
+        before - self.taken
+    }
+}
+
+/// A sparse counterpart to [`AssociatedData`], for when only a few keys carry
+/// data. Values are stored in a map from index to `(version, value)`, so
+/// memory scales with the number of entries rather than the highest index.
+#[cfg(feature = "std")]
+pub struct SparseSecondaryMap<K: Key<N, Idx>, V, N, Idx: SlotInt = u32> {
+    items: HashMap<Idx, (Idx::Version, V)>,
+    __phantom: core::marker::PhantomData<fn() -> (K, N)>,
+}
+
+#[cfg(feature = "std")]
+impl<K: Key<N, Idx>, V, N, Idx: SlotInt> Default for SparseSecondaryMap<K, V, N, Idx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Key<N, Idx>, V, N, Idx: SlotInt> SparseSecondaryMap<K, V, N, Idx> {
+    pub fn new() -> Self {
+        Self {
+            items: HashMap::new(),
+            __phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Inserts `value` for `key`, following the same rules as
+    /// `AssociatedData::insert`: a value stored under an older version is
+    /// replaced, but one stored under a newer version is left alone.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let data = key.data();
+        match self.items.get_mut(&data.index) {
+            Some((version, old)) if *version == data.version => {
+                Some(core::mem::replace(old, value))
+            }
+            Some((version, _)) if *version > data.version => None,
+            _ => {
+                self.items.insert(data.index, (data.version, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let data = key.data();
+        match self.items.get(&data.index) {
+            Some((version, _)) if *version == data.version => {
+                self.items.remove(&data.index).map(|(_, value)| value)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let data = key.data();
+        match self.items.get(&data.index) {
+            Some((version, value)) if *version == data.version => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let data = key.data();
+        match self.items.get_mut(&data.index) {
+            Some((version, value)) if *version == data.version => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Iterates entries in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (K, &V)> {
+        self.items
+            .iter()
+            .map(|(&index, (version, value))| (K::init(*version, index), value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (K, &mut V)> {
+        self.items
+            .iter_mut()
+            .map(|(&index, (version, value))| (K::init(*version, index), value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Key<N, Idx>, V: Debug, N, Idx: SlotInt> Debug for SparseSecondaryMap<K, V, N, Idx> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut dbg = f.debug_struct(&format!(
+            "SparseSecondaryMap<{}, {}>",
+            core::any::type_name::<K>(),
+            core::any::type_name::<V>()
+        ));
+        let mut entries: Vec<_> = self.items.iter().collect();
+        entries.sort_unstable_by_key(|(&index, _)| index);
+        entries.into_iter().for_each(|(index, (version, value))| {
+            dbg.field(&format!("{}v{}", index, version), value);
+        });
+        dbg.finish()
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use super::*;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    impl<T, Idx: SlotInt + Serialize> Serialize for KeyData<T, Idx>
+    where
+        Idx::Version: Serialize,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            (self.index, self.version).serialize(serializer)
+        }
+    }
+
+    impl<'de, T, Idx: SlotInt + Deserialize<'de>> Deserialize<'de> for KeyData<T, Idx>
+    where
+        Idx::Version: Deserialize<'de>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let (index, version) = <(Idx, Idx::Version)>::deserialize(deserializer)?;
+            Ok(Self {
+                index,
+                version,
+                __phantom: core::marker::PhantomData,
+            })
+        }
+    }
+
+    impl<T> Serialize for DefaultKey<T> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.data.serialize(serializer)
+        }
+    }
+
+    impl<'de, T> Deserialize<'de> for DefaultKey<T> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(Self {
+                data: KeyData::deserialize(deserializer)?,
+            })
+        }
+    }
+
+    #[derive(Serialize)]
+    #[serde(bound = "V: Serialize, Idx: Serialize, Idx::Version: Serialize")]
+    struct SlabRef<'a, V, Idx: SlotInt> {
+        slots: &'a [Slot<V, Idx>],
+        free: &'a [Idx],
+        fresh_version: Idx::Version,
+        generation: u64,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(bound = "V: Deserialize<'de>, Idx: Deserialize<'de>, Idx::Version: Deserialize<'de>")]
+    struct SlabRepr<V, Idx: SlotInt> {
+        slots: Vec<Slot<V, Idx>>,
+        free: Vec<Idx>,
+        /// Missing from data written before it was serialized.
+        #[serde(default)]
+        fresh_version: Option<Idx::Version>,
+        #[serde(default)]
+        generation: u64,
+    }
+
+    /// Slabs serialize every slot, vacant ones included, along with the free
+    /// list and the version for newly pushed slots, so indices, versions and
+    /// reuse order survive a round-trip, even after `shrink_to_fit`.
+    impl<K: Key<V, Idx>, V: Serialize, Idx: SlotInt + Serialize> Serialize for Slab<K, V, Idx>
+    where
+        Idx::Version: Serialize,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            SlabRef {
+                slots: &self.values,
+                free: &self.free,
+                fresh_version: self.fresh_version,
+                generation: self.generation,
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de, K, V, Idx> Deserialize<'de> for Slab<K, V, Idx>
+    where
+        K: Key<V, Idx> + Clone,
+        V: Deserialize<'de>,
+        Idx: SlotInt + Deserialize<'de>,
+        Idx::Version: Deserialize<'de>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let repr = SlabRepr::deserialize(deserializer)?;
+            let taken = repr.slots.iter().filter(|v| v.is_occupied()).count();
+            let fresh_version = repr.fresh_version.unwrap_or(Idx::FRESH_VERSION);
+            if !Idx::get(fresh_version).to_usize().is_multiple_of(2) {
+                return Err(D::Error::custom("fresh_version must be even"));
+            }
+            let slab = Slab {
+                values: repr.slots,
+                free: repr.free,
+                taken,
+                fresh_version,
+                generation: repr.generation,
+                counters: Counters {
+                    inserted: taken as u64,
+                    recycled: 0,
+                    peak_len: taken,
+                },
+                __phantom: core::marker::PhantomData,
+            };
+            slab.validate().map_err(D::Error::custom)?;
+            Ok(slab)
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+mod rayon_impls {
+    use super::*;
+    use rayon::prelude::*;
+
+    impl<K: Key<V, Idx> + Clone + Send, V: Send + Sync, Idx: SlotInt> Slab<K, V, Idx> {
+        pub fn par_iter(&self) -> impl ParallelIterator<Item = (K, &V)> {
+            let generation = self.generation;
+            self.values
+                .par_iter()
+                .enumerate()
+                .filter(|(_, v)| v.is_occupied())
+                .map(move |(i, v)| {
+                    (
+                        K::init_with_generation(v.version, Idx::from_usize(i), generation),
+                        v.value.as_ref().unwrap(),
+                    )
+                })
+        }
+
+        pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = (K, &mut V)> {
+            let generation = self.generation;
+            self.values
+                .par_iter_mut()
+                .enumerate()
+                .filter(|(_, v)| v.is_occupied())
+                .map(move |(i, v)| {
+                    (
+                        K::init_with_generation(v.version, Idx::from_usize(i), generation),
+                        v.value.as_mut().unwrap(),
+                    )
+                })
+        }
+
+        pub fn par_values_mut(&mut self) -> impl ParallelIterator<Item = &mut V> {
+            self.values
+                .par_iter_mut()
+                .filter(|v| v.is_occupied())
+                .map(|v| v.value.as_mut().unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::eprintln;
+
+    use super::*;
+
+    #[test]
+    fn any() {
+        let mut map = AnySlab::new();
+        let mut keys = vec![];
+        let mut keys2 = vec![];
+        (0..10).for_each(|i| {
+            let k = map.insert(i);
+            keys.push(k);
+        });
+        (0..10).for_each(|i| {
+            let k = map.insert(format!("test {}", i));
+            keys2.push(k);
+        });
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(map.get(k), Some(&i));
+        }
+        for k in keys2.iter() {
+            let v = map.get(k);
+            eprintln!("{v:?}");
+            assert!(v.is_some(), "{v:?}");
+        }
+        assert_eq!(map.len(), 20);
+    }
+
+    #[test]
+    fn any_typed_access() {
+        let mut map = AnySlab::new();
+        let n = map.insert(5u32);
+        let s = map.insert(String::from("five"));
+        let as_string = DefaultKey::<String>::init(n.version(), n.index());
+        assert_eq!(map.get(&as_string), None);
+        assert_eq!(map.remove(&as_string), None);
+        assert_eq!(map.len(), 2);
+
+        *map.get_mut(&n).unwrap() += 1;
+        assert_eq!(map.remove(&n), Some(6));
+        assert_eq!(map.get(&n), None);
+        assert_eq!(map.remove(&n), None);
+
+        let reused = map.insert(1.5f64);
+        assert_eq!(reused.index(), n.index());
+        assert_eq!(map.get(&n), None);
+        assert_eq!(map.get(&reused), Some(&1.5));
+        assert_eq!(map.get(&s).map(String::as_str), Some("five"));
+    }
+
+    #[test]
+    fn associated() {
+        let mut map = Slab::default();
+        let mut associated = AssociatedData::new();
+        let mut keys = vec![];
+        (0..10).for_each(|i| {
+            let k = map.insert(i);
+            if i % 2 == 0 {
+                associated.insert(k, map.len());
+            }
+            keys.push(k);
+        });
+        assert_eq!(associated.len(), 5, "{associated:?}");
+        assert_eq!(associated.get(keys[0]), Some(&1), "{associated:?}");
+        // assert!(false, "\n{associated:#?}\n{map:#?}")
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut map = Slab::default();
+        (0..10).for_each(|i| {
+            map.insert(i);
+        });
+        let mut iter = map.into_iter();
+        assert_eq!(iter.next(), Some(0));
+    }
+
+    #[test]
+    fn iter() {
+        let mut map = Slab::default();
+        (0..10).for_each(|i| {
+            map.insert(i);
+        });
+        let mut iter = map.iter();
+        assert_eq!(iter.next().map(|v| v.1), Some(&0), "{map:?}");
+    }
+
+    #[test]
+    fn clear() {
+        let mut map = Slab::default();
+        (0..10).for_each(|i| {
+            map.insert(i);
+        });
+        assert_eq!(map.capacity(), 16, "{map:?}");
+        map.clear();
+        assert_eq!(map.len(), 0, "{map:?}");
+        assert_eq!(map.capacity(), 16, "{map:?}");
+        (0..10).for_each(|i| {
+            map.insert(i);
+        });
+        assert_eq!(map.len(), 10, "{map:?}");
+        assert_eq!(map.capacity(), 16, "{map:?}");
+    }
+
+    #[test]
+    fn values_mut_except() {
+        let mut map = Slab::default();
+        let keys = (0..5).map(|i| map.insert(i)).collect::<Vec<_>>();
+        let visited = map
+            .values_mut_except(&[keys[1], keys[3]])
+            .map(|(k, v)| {
+                *v += 10;
+                k.index()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(visited, vec![0, 2, 4]);
+        assert_eq!(map.get(keys[0]), Some(&10));
+        assert_eq!(map.get(keys[1]), Some(&1));
+        assert_eq!(map.get(keys[3]), Some(&3));
+    }
+
+    #[test]
+    fn for_each_with_component() {
+        let mut map = Slab::default();
+        let mut health = AssociatedData::new();
+        let keys = (0..4).map(|i| map.insert(i)).collect::<Vec<_>>();
+        health.insert(keys[1], 100);
+        map.for_each_with_component(&mut health, |_, v, hp| *hp += *v);
+        assert_eq!(health.len(), 4);
+        assert_eq!(health.get(keys[0]), Some(&0));
+        assert_eq!(health.get(keys[1]), Some(&101));
+        assert_eq!(health.get(keys[3]), Some(&3));
+
+        let mut bounded = AssociatedData::with_bound(1);
+        let mut seen = vec![];
+        map.for_each_with_component(&mut bounded, |k, _, hp: &mut i32| {
+            seen.push(k);
+            *hp += 1;
+        });
+        assert_eq!(seen, keys[..2]);
+        assert_eq!(bounded.len(), 2);
+        assert_eq!(bounded.get(keys[2]), None);
+    }
+
+    #[test]
+    fn binary_search_by() {
+        let mut map = Slab::default();
+        let keys = [1, 3, 5, 7, 9].map(|i| map.insert(i));
+        let found = map.binary_search_by(|v| v.cmp(&7)).unwrap();
+        assert_eq!(found.index(), keys[3].index());
+        assert_eq!(map.get(found), Some(&7));
+        assert_eq!(map.binary_search_by(|v| v.cmp(&4)).unwrap_err(), 2);
+        assert_eq!(map.binary_search_by(|v| v.cmp(&10)).unwrap_err(), 5);
+
+        map.remove(keys[1]);
+        assert_eq!(map.binary_search_by(|_| unreachable!()).unwrap_err(), 5);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn dedup_by_key() {
+        let mut map = Slab::default();
+        let keys = [1, 2, 1, 3, 2, 1].map(|i| map.insert(i));
+        assert_eq!(map.dedup_by_key(|v| *v), 3);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(keys[0]), Some(&1));
+        assert_eq!(map.get(keys[1]), Some(&2));
+        assert_eq!(map.get(keys[2]), None);
+        assert_eq!(map.get(keys[3]), Some(&3));
+        assert_eq!(map.get(keys[4]), None);
+        assert_eq!(map.get(keys[5]), None);
+    }
+
+    #[test]
+    fn occupancy_bitmap() {
+        let mut map = Slab::default();
+        let keys = (0..70).map(|i| map.insert(i)).collect::<Vec<_>>();
+        map.remove(keys[3]);
+        let bitmap = map.occupancy_bitmap();
+        assert_eq!(bitmap.len(), 2);
+        assert_eq!(bitmap[0], !(1 << 3));
+        assert_eq!(bitmap[1], (1 << 6) - 1);
+
+        let mut target = bitmap.clone();
+        target[0] &= !(1 << 10);
+        target[1] &= !(1 << 4);
+        map.apply_removals_from_bitmap(&target);
+        assert_eq!(map.len(), 67);
+        assert_eq!(map.get(keys[10]), None);
+        assert_eq!(map.get(keys[68]), None);
+        assert_eq!(map.get(keys[11]), Some(&11));
+        assert_eq!(map.occupancy_bitmap(), target);
+    }
+
+    #[test]
+    fn with_prepared_slots() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::with_prepared_slots(4);
+        let capacity = map.capacity();
+        assert_eq!(map.len(), 0);
+        for i in 0..4 {
+            let k = map.insert(i);
+            assert_eq!(k.index(), i as u32);
+            assert_eq!(map.get(k), Some(&i));
+        }
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(map.insert(4).index(), 4);
+    }
+
+    #[test]
+    fn iter_from() {
+        let mut map = Slab::default();
+        let keys = (0..10).map(|i| map.insert(i)).collect::<Vec<_>>();
+        map.remove(keys[4]);
+        let mut seen = vec![];
+        let mut cursor = None;
+        loop {
+            let page = map.iter_from(cursor).take(3).collect::<Vec<_>>();
+            if page.is_empty() {
+                break;
+            }
+            cursor = page.last().map(|(k, _)| *k);
+            seen.extend(page.into_iter().map(|(_, v)| *v));
+        }
+        assert_eq!(seen, vec![0, 1, 2, 3, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn stats_delta() {
+        let mut map = Slab::default();
+        let keys = (0..4).map(|i| map.insert(i)).collect::<Vec<_>>();
+        let before = map.stats();
+        assert_eq!(before.len, 4);
+        assert_eq!(before.inserted, 4);
+        map.remove(keys[0]);
+        map.remove(keys[1]);
+        map.insert(10);
+        map.insert(11);
+        map.insert(12);
+        let after = map.stats();
+        assert_eq!(after.peak_len, 5);
+        assert_eq!(
+            map.stats_delta(&before),
+            StatsDelta {
+                inserts: 3,
+                removes: 2,
+                recycled: 2,
+                growth: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn retain_range() {
+        let mut map = Slab::default();
+        let keys = (0..10).map(|i| map.insert(i)).collect::<Vec<_>>();
+        assert_eq!(map.retain_range(0..5, |_, v| *v % 2 == 0), 2);
+        assert_eq!(map.len(), 8);
+        assert_eq!(map.get(keys[1]), None);
+        assert_eq!(map.get(keys[3]), None);
+        assert_eq!(map.get(keys[2]), Some(&2));
+        for k in &keys[5..] {
+            assert_eq!(map.get(*k), Some(&(k.index() as i32)));
+        }
+        assert_eq!(map.retain_range(8.., |_, _| false), 2);
+        assert_eq!(map.len(), 6);
+        assert_eq!(map.retain_range(20.., |_, _| false), 0);
+        assert_eq!(map.get(keys[7]), Some(&7));
+    }
+
+    #[test]
+    fn slot_lookup() {
+        let mut map = Slab::default();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        assert_eq!(map.slot(&a).and_then(|s| s.value.as_ref()), Some(&1));
+        assert!(map.slot_mut(&b).is_some());
+        assert_eq!(map.remove(a), Some(1));
+        assert!(map.slot(&a).is_none());
+        assert_eq!(map.remove(a), None);
+        assert_eq!(map.len(), 1);
+        *map.get_mut(b).unwrap() = 3;
+        assert_eq!(map.get(b), Some(&3));
+
+        let vacant = DefaultKey::init(NonZeroU32::new(3).unwrap(), a.index());
+        assert!(map.slot(&vacant).is_none());
+        assert_eq!(map.remove(vacant), None);
+        let out_of_bounds = DefaultKey::init(NonZeroU32::new(2).unwrap(), 100);
+        assert!(map.slot(&out_of_bounds).is_none());
+        assert_eq!(map.get(out_of_bounds), None);
+    }
+
+    #[test]
+    fn from_keyed() {
+        let mut map = Slab::default();
+        let keys = (0..6).map(|i| map.insert(i)).collect::<Vec<_>>();
+        map.remove(keys[1]);
+        map.remove(keys[4]);
+        let k1 = map.insert(10);
+        let pairs = map.iter().map(|(k, v)| (k, *v)).collect::<Vec<_>>();
+
+        let mut rebuilt: Slab<DefaultKey<i32>, i32> = Slab::from_keyed(pairs.clone()).unwrap();
+        assert_eq!(rebuilt.len(), 5);
+        for (k, v) in &pairs {
+            assert_eq!(rebuilt.get(*k), Some(v));
+        }
+        assert_eq!(rebuilt.get(k1), Some(&10));
+        assert_eq!(rebuilt.insert(20).index(), 1);
+
+        let dup = vec![(keys[0], 0), (keys[0], 1)];
+        assert_eq!(
+            Slab::from_keyed(dup).unwrap_err(),
+            FromKeyedError::DuplicateIndex(0)
+        );
+        let odd = DefaultKey::init(NonZeroU32::new(3).unwrap(), 2);
+        assert_eq!(
+            Slab::from_keyed(vec![(odd, 0)]).unwrap_err(),
+            FromKeyedError::InvalidVersion(2)
+        );
+    }
+
+    #[test]
+    fn apply_associated() {
+        let mut map = Slab::default();
+        let mut deltas = AssociatedData::new();
+        let keys = (0..4).map(|i| map.insert(i * 10)).collect::<Vec<_>>();
+        deltas.insert(keys[0], 1);
+        deltas.insert(keys[2], 2);
+        deltas.insert(keys[3], 3);
+        map.remove(keys[3]);
+        map.apply_associated(&deltas, |v, d| *v += d);
+        assert_eq!(map.get(keys[0]), Some(&1));
+        assert_eq!(map.get(keys[1]), Some(&10));
+        assert_eq!(map.get(keys[2]), Some(&22));
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn capacity_breakdown() {
+        let mut map = Slab::with_capacity(8);
+        let keys = (0..5)
+            .map(|i| map.insert(i))
+            .collect::<Vec<DefaultKey<_>>>();
+        map.remove(keys[1]);
+        map.remove(keys[2]);
+        let breakdown = map.capacity_breakdown();
+        assert_eq!(breakdown.free_slots, 2);
+        assert_eq!(breakdown.spare_capacity, map.capacity() - 5);
+        let capacity = map.capacity();
+        for i in 0..breakdown.total() {
+            map.insert(i as i32);
+        }
+        assert_eq!(map.capacity(), capacity);
+    }
+
+    #[test]
+    fn replay() {
+        let map: Slab<DefaultKey<i32>, i32> = Slab::replay(&[
+            Op::Insert(1),
+            Op::Insert(2),
+            Op::Insert(3),
+            Op::Remove(1),
+            Op::Remove(7),
+            Op::Insert(4),
+            Op::Remove(0),
+            Op::Insert(6),
+        ]);
+        assert_eq!(map.validate(), Ok(()));
+        assert_eq!(map.len(), 3);
+        let mut values = map.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(values, vec![3, 4, 6]);
+
+        let cleared: Slab<DefaultKey<i32>, i32> =
+            Slab::replay(&[Op::Insert(1), Op::Clear, Op::Insert(2), Op::Remove(0)]);
+        assert_eq!(cleared.validate(), Ok(()));
+        assert_eq!(cleared.len(), 1);
+
+        let retained: Slab<DefaultKey<i32>, i32> = Slab::replay(&[
+            Op::Insert(1),
+            Op::Insert(2),
+            Op::Insert(3),
+            Op::Remove(0),
+            Op::Retain(|v| *v != 3),
+        ]);
+        assert_eq!(retained.validate(), Ok(()));
+        assert_eq!(retained.len(), 1);
+    }
+
+    #[test]
+    fn split_at_index_mut() {
+        let mut map = Slab::default();
+        let keys = (0..8).map(|i| map.insert(i)).collect::<Vec<_>>();
+        map.remove(keys[2]);
+        let (mut front, mut back) = map.split_at_index_mut(4);
+        assert_eq!((front.offset(), back.offset()), (0, 4));
+        assert_eq!((front.len(), back.len()), (3, 4));
+        front.values_mut().for_each(|v| *v += 100);
+        back.values_mut().for_each(|v| *v *= -1);
+        assert_eq!(
+            front.values().copied().collect::<Vec<_>>(),
+            vec![100, 101, 103]
+        );
+        assert_eq!(
+            back.values().copied().collect::<Vec<_>>(),
+            vec![-4, -5, -6, -7]
+        );
+        assert_eq!(map.get(keys[3]), Some(&103));
+        assert_eq!(map.get(keys[4]), Some(&-4));
+
+        let (front, back) = map.split_at_index_mut(100);
+        assert_eq!(front.len(), 7);
+        assert!(back.is_empty());
+    }
+
+    #[test]
+    fn key_validate() {
+        #[derive(Clone, Copy, Debug)]
+        struct TaggedKey<T> {
+            data: KeyData<T>,
+            tag: u8,
+        }
+
+        impl<T> Key<T> for TaggedKey<T> {
+            fn data(&self) -> &KeyData<T> {
+                &self.data
+            }
+
+            fn init(version: NonZeroU32, index: u32) -> Self {
+                Self {
+                    data: KeyData {
+                        index,
+                        version,
+                        __phantom: core::marker::PhantomData,
+                    },
+                    tag: 7,
+                }
+            }
+
+            fn validate(&self, slot_version: NonZeroU32) -> bool {
+                self.tag == 7 && self.version() == slot_version
+            }
+        }
+
+        let mut map: Slab<TaggedKey<i32>, i32> = Slab::new();
+        let k = map.insert(1);
+        assert_eq!(map.get(k), Some(&1));
+        let forged = TaggedKey { tag: 0, ..k };
+        assert_eq!(map.get(forged), None);
+        assert_eq!(map.remove(forged), None);
+        assert_eq!(map.remove(k), Some(1));
+    }
+
+    #[test]
+    fn get_mut_with_spawner() {
+        let mut map = Slab::default();
+        let keys = (0..4).map(|i| map.insert(i)).collect::<Vec<_>>();
+        map.remove(keys[1]);
+        let spawned = map
+            .get_mut_with_spawner(keys[2], |v, spawner| {
+                let a = spawner.spawn(*v * 10);
+                *v += 1;
+                let b = spawner.spawn(*v * 10);
+                assert_eq!(spawner.pending(), 2);
+                (a, b)
+            })
+            .unwrap();
+        assert_eq!(map.get(keys[2]), Some(&3));
+        assert_eq!(map.get(spawned.0), Some(&20));
+        assert_eq!(map.get(spawned.1), Some(&30));
+        assert_eq!(spawned.0.index(), 1);
+        assert_eq!(spawned.1.index(), 4);
+        assert_eq!(map.len(), 5);
+        assert!(map.get_mut_with_spawner(keys[1], |_, _| ()).is_none());
+    }
+
+    #[test]
+    fn foreign_key() {
+        let mut small = Slab::default();
+        let mut large = Slab::default();
+        small.insert(0);
+        let foreign = (0..100).map(|i| large.insert(i)).last().unwrap();
+        assert_eq!(small.get(foreign), None);
+        assert_eq!(small.get_mut(foreign), None);
+        assert_eq!(small.remove(foreign), None);
+        let forged = DefaultKey::init(NonZeroU32::new(2).unwrap(), u32::MAX);
+        assert_eq!(small.get(forged), None);
+        assert_eq!(small.get_mut(forged), None);
+        assert_eq!(small.remove(forged), None);
+        assert_eq!(small.len(), 1);
+    }
+
+    #[test]
+    fn values() {
+        let mut map = Slab::default();
+        let keys = (0..10).map(|i| map.insert(i)).collect::<Vec<_>>();
+        assert_eq!(map.values().count(), 10);
+        map.values_mut().for_each(|v| *v *= 2);
+        assert_eq!(map.values().sum::<i32>(), 90);
+        map.remove(keys[3]);
+        assert_eq!(map.values().count(), 9);
+        assert_eq!(map.values_mut().count(), 9);
+    }
+
+    #[test]
+    fn contains_key() {
+        let mut map = Slab::default();
+        let mut associated = AssociatedData::new();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        associated.insert(a, "a");
+        let value = map.get(b).unwrap();
+        assert!(map.contains_key(a));
+        assert!(map.contains_key(b));
+        assert_eq!(value, &2);
+        assert!(associated.contains_key(a));
+        assert!(!associated.contains_key(b));
+
+        map.remove(a);
+        let reused = map.insert(3);
+        assert_eq!(reused.index(), a.index());
+        assert!(!map.contains_key(a));
+        associated.insert(reused, "reused");
+        assert!(!associated.contains_key(a));
+        assert!(associated.contains_key(reused));
+
+        let past_end = DefaultKey::init(NonZeroU32::new(2).unwrap(), 50);
+        assert!(!map.contains_key(past_end));
+        assert!(!associated.contains_key(past_end));
+    }
+
+    #[test]
+    fn get_disjoint_mut() {
+        let mut map = Slab::default();
+        let keys = (0..4).map(|i| map.insert(i)).collect::<Vec<_>>();
+        let [a, b, c] = map.get_disjoint_mut([keys[0], keys[3], keys[1]]).unwrap();
+        std::mem::swap(a, b);
+        *c += 10;
+        assert_eq!(map.get(keys[0]), Some(&3));
+        assert_eq!(map.get(keys[3]), Some(&0));
+        assert_eq!(map.get(keys[1]), Some(&11));
+
+        assert!(map.get_disjoint_mut([keys[0], keys[0]]).is_none());
+        map.remove(keys[2]);
+        assert!(map.get_disjoint_mut([keys[0], keys[2]]).is_none());
+        let past_end = DefaultKey::init(NonZeroU32::new(2).unwrap(), 9);
+        assert!(map.get_disjoint_mut([past_end]).is_none());
+        assert!(map.get_disjoint_mut::<0>([]).is_some());
+    }
+
+    #[test]
+    fn associated_entry() {
+        let mut map = Slab::default();
+        let mut associated = AssociatedData::new();
+        let a = map.insert(0);
+        let b = map.insert(1);
+
+        *associated.entry(a).or_insert(1) += 1;
+        assert_eq!(associated.get(a), Some(&2));
+        associated.entry(a).and_modify(|v| *v *= 10).or_insert(0);
+        assert_eq!(associated.get(a), Some(&20));
+        match associated.entry(a) {
+            Entry::Occupied(mut entry) => assert_eq!(entry.insert(5), 20),
+            _ => panic!("expected an occupied entry"),
+        }
+        assert_eq!(associated.get(a), Some(&5));
+        assert_eq!(*associated.entry(b).or_insert_with(|| 7), 7);
+        assert_eq!(associated.len(), 2);
+
+        map.remove(a);
+        let newer = map.insert(2);
+        assert_eq!(*associated.entry(newer).or_insert(9), 9);
+        assert_eq!(associated.len(), 2);
+        match associated.entry(a) {
+            Entry::Rejected(entry) => {
+                assert_eq!(entry.reason(), Rejection::NewerVersion);
+                assert_eq!(entry.key(), &a);
+            }
+            _ => panic!("expected a rejected entry"),
+        }
+        assert_eq!(associated.get(newer), Some(&9));
+
+        match associated.entry(b) {
+            Entry::Occupied(entry) => assert_eq!(entry.remove(), 7),
+            _ => panic!("expected an occupied entry"),
+        }
+        assert_eq!(associated.len(), 1);
+    }
+
+    #[test]
+    fn drain() {
+        let mut map = Slab::default();
+        let keys = (0..10).map(|i| map.insert(i)).collect::<Vec<_>>();
+        map.remove(keys[2]);
+        let capacity = map.capacity();
+        let drained = map.drain().collect::<Vec<_>>();
+        assert_eq!(drained.len(), 9);
+        assert_eq!(drained[2].0.index(), 3);
+        assert_eq!(drained[2].1, 3);
+        assert_eq!(map.len(), 0);
+        assert_eq!(map.capacity(), capacity);
+        assert!(keys.iter().all(|k| map.get(*k).is_none()));
+        assert_eq!(map.validate(), Ok(()));
+
+        let keys = (0..10).map(|i| map.insert(i)).collect::<Vec<_>>();
+        assert_eq!(map.capacity(), capacity);
+        let mut drain = map.drain();
+        assert_eq!(drain.size_hint(), (10, Some(10)));
+        assert!(drain.next().is_some());
+        drop(drain);
+        assert_eq!(map.len(), 0);
+        assert!(keys.iter().all(|k| map.get(*k).is_none()));
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut map = Slab::default();
+        let keys = (0..6)
+            .map(|i| map.insert(i.to_string()))
+            .collect::<Vec<_>>();
+        map.remove(keys[1]);
+        map.remove(keys[4]);
+        let json = serde_json::to_string(&(&map, &keys)).unwrap();
+
+        type Saved = (Slab<DefaultKey<String>, String>, Vec<DefaultKey<String>>);
+        let (mut loaded, loaded_keys): Saved = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.len(), 4);
+        for (k, loaded_k) in keys.iter().zip(&loaded_keys) {
+            assert_eq!(k.data(), loaded_k.data());
+            assert_eq!(loaded.get(*loaded_k), map.get(*k));
+        }
+        assert_eq!(
+            loaded.insert("a".into()).index(),
+            map.insert("a".into()).index()
+        );
+        assert_eq!(
+            loaded.insert("b".into()).index(),
+            map.insert("b".into()).index()
+        );
+
+        let corrupt = r#"{"slots":[{"version":2,"value":null}],"free":[]}"#;
+        assert!(serde_json::from_str::<Slab<DefaultKey<String>, String>>(corrupt).is_err());
+    }
+
+    #[test]
+    fn clone() {
+        let mut map = Slab::default();
+        let keys = (0..4)
+            .map(|i| map.insert(i.to_string()))
+            .collect::<Vec<_>>();
+        map.remove(keys[1]);
+        let mut copy = map.clone();
+        for k in &keys {
+            assert_eq!(copy.get(*k), map.get(*k));
+        }
+        assert_eq!(copy.get(keys[1]), None);
+        assert_eq!(copy.len(), 3);
+        let reused = copy.insert("new".into());
+        assert_eq!(reused.index(), keys[1].index());
+        assert_eq!(copy.get(keys[1]), None);
+        assert_eq!(map.get(reused), None);
+        assert_eq!(map.insert("new".into()).data(), reused.data());
+    }
+
+    #[test]
+    fn reserve_and_shrink() {
+        let mut map = Slab::default();
+        let keys = (0..10).map(|i| map.insert(i)).collect::<Vec<_>>();
+        map.remove(keys[2]);
+        map.reserve(20);
+        assert!(map.capacity_breakdown().total() >= 20);
+        let capacity = map.capacity();
+        for i in 0..20 {
             map.insert(i);
+        }
+        assert_eq!(map.capacity(), capacity);
+
+        let mut map = Slab::default();
+        let keys = (0..10).map(|i| map.insert(i)).collect::<Vec<_>>();
+        for k in &keys[4..] {
+            map.remove(*k);
+        }
+        map.remove(keys[1]);
+        map.shrink_to_fit();
+        assert_eq!(map.stats().slots, 4);
+        assert_eq!(map.capacity(), 4);
+        assert_eq!(map.validate(), Ok(()));
+        for k in [keys[0], keys[2], keys[3]] {
+            assert_eq!(map.get(k), Some(&(k.index() as i32)));
+        }
+        assert_eq!(map.insert(1).index(), 1);
+        let pushed = (0..6).map(|i| map.insert(i)).collect::<Vec<_>>();
+        assert_eq!(pushed[5].index(), 9);
+        for k in &keys[4..] {
+            assert_eq!(map.get(*k), None);
+        }
+    }
+
+    #[test]
+    fn insert_with_key() {
+        struct Node {
+            self_key: DefaultKey<Node>,
+        }
+
+        let mut map = Slab::default();
+        let first = map.insert_with_key(|self_key| Node { self_key });
+        let second = map.insert_with_key(|self_key| Node { self_key });
+        assert_eq!(map.get(first).unwrap().self_key.data(), first.data());
+        map.remove(first);
+        let reused = map.insert_with_key(|self_key| Node { self_key });
+        assert_eq!(reused.index(), first.index());
+        assert_eq!(map.get(reused).unwrap().self_key.data(), reused.data());
+        assert_eq!(map.get(second).unwrap().self_key.data(), second.data());
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn clear_after_remove() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::with_capacity(16);
+        let keys = (0..10).map(|i| map.insert(i)).collect::<Vec<_>>();
+        map.remove(keys[1]);
+        map.remove(keys[5]);
+        map.clear();
+        assert_eq!(map.validate(), Ok(()));
+        let keys = (0..32).map(|i| map.insert(i)).collect::<Vec<_>>();
+        assert_eq!(keys[0].index(), 0);
+        assert_eq!(keys[9].index(), 9);
+        let mut indices = keys.iter().map(|k| k.index()).collect::<Vec<_>>();
+        indices.sort();
+        indices.dedup();
+        assert_eq!(indices.len(), 32);
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(map.get(*k), Some(&(i as i32)));
+        }
+        assert_eq!(map.len(), 32);
+    }
+
+    #[test]
+    fn version_exhaustion() {
+        let mut map = Slab::default();
+        let k = map.insert(0);
+        map.remove(k);
+        map.values[0].version = NonZeroU32::new(u32::MAX - 2).unwrap();
+        let last = map.insert(1);
+        assert_eq!(last.index(), 0);
+        assert_eq!(last.version().get(), u32::MAX - 1);
+        assert_eq!(map.remove(last), Some(1));
+        assert!(map.free.is_empty());
+        assert!(map.values[0].retired() && map.values[0].is_vacant());
+        assert_eq!(map.retired_slots(), 1);
+        for i in 0..4 {
+            let k = map.insert(i);
+            assert_ne!(k.index(), 0);
+            assert_eq!(map.get(last), None);
+            map.remove(k);
+        }
+        map.clear();
+        assert!(!map.free.contains(&0));
+        map.retain(|_, _| false);
+        assert_eq!(map.get(last), None);
+        assert_eq!(map.get(k), None);
+        assert_eq!(map.validate(), Ok(()));
+        map.free.push(0);
+        assert_eq!(map.validate(), Err("free list contains a retired slot"));
+    }
+
+    #[test]
+    fn keys() {
+        let mut map = Slab::default();
+        let mut data = AssociatedData::new();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        let c = map.insert(3);
+        map.remove(b);
+        data.insert(a, "a");
+        data.insert(c, "c");
+
+        let keys: Vec<_> = map.keys().collect();
+        assert_eq!(keys.iter().map(|k| k.index()).collect::<Vec<_>>(), [0, 2]);
+        for key in &keys {
+            *map.get_mut(*key).unwrap() *= 10;
+        }
+        assert_eq!(map.values().copied().collect::<Vec<_>>(), [10, 30]);
+
+        let keys: Vec<_> = data.keys().collect();
+        assert_eq!(keys.len(), 2);
+        assert!(keys.iter().all(|k| map.contains_key(*k)));
+    }
+
+    #[test]
+    fn index() {
+        let mut map = Slab::default();
+        let k = map.insert(1);
+        map[k] += 1;
+        assert_eq!(map[k], 2);
+
+        map.remove(k);
+        let stale = std::panic::catch_unwind(|| map[k]).unwrap_err();
+        let message = stale.downcast_ref::<String>().unwrap();
+        assert!(message.starts_with("invalid or stale key"));
+        assert!(message.contains("0v2"));
+    }
+
+    #[test]
+    fn exact_size_iterators() {
+        let mut map = Slab::default();
+        let keys: Vec<_> = (0..5).map(|i| map.insert(i)).collect();
+        map.remove(keys[1]);
+        map.remove(keys[3]);
+
+        let mut iter = map.iter();
+        for remaining in (0..3).rev() {
+            assert!(iter.next().is_some());
+            assert_eq!(iter.len(), remaining);
+            assert_eq!(iter.size_hint(), (remaining, Some(remaining)));
+        }
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+
+        assert_eq!(map.keys().len(), 3);
+        assert_eq!(map.values().len(), 3);
+        assert_eq!(map.iter_mut().len(), 3);
+        let mut values = map.values_mut();
+        values.next();
+        assert_eq!(values.len(), 2);
+        assert_eq!(map.drain().len(), 3);
+    }
+
+    #[test]
+    fn iter_rev() {
+        let mut map = Slab::default();
+        let keys: Vec<_> = (0..6).map(|i| map.insert(i)).collect();
+        map.remove(keys[2]);
+        map.remove(keys[3]);
+        map.remove(keys[5]);
+
+        let rev: Vec<_> = map.iter().rev().map(|(k, _)| k.index()).collect();
+        assert_eq!(rev, [4, 1, 0]);
+        assert_eq!(map.values().rev().copied().collect::<Vec<_>>(), [4, 1, 0]);
+
+        let mut iter = map.iter_mut();
+        assert_eq!(iter.next().map(|(k, _)| k.index()), Some(0));
+        assert_eq!(iter.next_back().map(|(k, _)| k.index()), Some(4));
+        assert_eq!(iter.len(), 1);
+        assert_eq!(iter.next_back().map(|(k, _)| k.index()), Some(1));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn from_iter_and_extend() {
+        let mut map: Slab<DefaultKey<i32>, i32> = (0..3).collect();
+        assert_eq!(map.len(), 3);
+        map.extend(3..5);
+        assert_eq!(map.values().copied().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        assert!(map.capacity() >= 5);
+
+        let keys = map.extend_keys([10, 20]);
+        assert_eq!(map[keys[0]], 10);
+        assert_eq!(map[keys[1]], 20);
+
+        let data: AssociatedData<_, _, _> = map.iter().map(|(k, v)| (k, v * 2)).collect();
+        assert_eq!(data.len(), map.len());
+        assert_eq!(data.get(keys[1]), Some(&40));
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        let mut map = Slab::default();
+        let mut data = AssociatedData::new();
+        let _ = map.insert(0);
+        let k = map.insert(1);
+        let mut calls = 0;
+        *data.get_or_insert_with(k, || {
+            calls += 1;
+            10
+        }) += 1;
+        let value = *data.get_or_insert_with(k, || {
+            calls += 1;
+            20
         });
-        let mut iter = map.into_iter();
-        assert_eq!(iter.next(), Some(0));
+        assert_eq!((value, calls), (11, 1));
+
+        map.remove(k);
+        let newer = map.insert(2);
+        assert_eq!(*data.get_or_insert_with(newer, || 30), 30);
+        assert_eq!(data.get(k), None);
+        assert_eq!(data.len(), 1);
+
+        assert_eq!(data.try_get_or_insert_with(k, || unreachable!()), None);
+        assert_eq!(data.try_get_or_insert_with(newer, || 40), Some(&mut 30));
+        let far = DefaultKey::init(NonZeroU32::new(2).unwrap(), 11);
+        let mut bounded = AssociatedData::<DefaultKey<i32>, i32, i32>::with_bound(10);
+        assert_eq!(bounded.try_get_or_insert_with(far, || 1), None);
+        let caught = std::panic::catch_unwind(move || *bounded.get_or_insert_with(far, || 1));
+        assert!(caught.is_err());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_iter() {
+        use rayon::prelude::*;
+
+        let mut map = Slab::default();
+        let keys: Vec<_> = (0..50_000u64).map(|i| map.insert(i)).collect();
+        keys.iter().step_by(3).for_each(|&k| {
+            map.remove(k);
+        });
+        let serial: u64 = map.values().sum();
+        assert_eq!(map.par_iter().map(|(_, v)| *v).sum::<u64>(), serial);
+
+        map.par_values_mut().for_each(|v| *v *= 2);
+        assert_eq!(map.values().sum::<u64>(), serial * 2);
+        map.par_iter_mut().for_each(|(k, v)| *v = k.index() as u64);
+        assert!(map.iter().all(|(k, v)| *v == k.index() as u64));
+    }
+
+    #[test]
+    fn slab_eq() {
+        let mut a = Slab::default();
+        let mut b = Slab::default();
+        assert_eq!(a, b);
+        let k = a.insert(1);
+        b.insert(1);
+        a.insert(2);
+        b.insert(2);
+        assert_eq!(a, b);
+
+        // Same values at the same indices, but under a newer version.
+        a.remove(k);
+        a.insert(1);
+        assert_ne!(a, b);
+
+        // Vacant slots don't matter.
+        b.insert(3);
+        let k = b.insert(4);
+        b.remove(k);
+        a.remove(a.keys().next().unwrap());
+        b.remove(b.keys().next().unwrap());
+        b.remove(b.keys().next_back().unwrap());
+        assert_eq!(a, b);
+        assert_ne!(a, Slab::default());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn sparse_secondary_map() {
+        let mut map = Slab::default();
+        let mut sparse = SparseSecondaryMap::new();
+        let keys: Vec<_> = (0..100).map(|i| map.insert(i)).collect();
+        assert_eq!(sparse.insert(keys[90], "a"), None);
+        assert_eq!(sparse.insert(keys[90], "b"), Some("a"));
+        assert_eq!(sparse.get(keys[90]), Some(&"b"));
+        assert_eq!(sparse.len(), 1);
+
+        map.remove(keys[90]);
+        let newer = map.insert(90);
+        assert_eq!(sparse.get(newer), None);
+        sparse.insert(newer, "c");
+        assert_eq!(sparse.get(keys[90]), None);
+        // A stale key can't clobber the newer value.
+        assert_eq!(sparse.insert(keys[90], "d"), None);
+        assert_eq!(sparse.remove(keys[90]), None);
+        *sparse.get_mut(newer).unwrap() = "e";
+        assert_eq!(
+            sparse
+                .iter()
+                .map(|(k, v)| (k.index(), *v))
+                .collect::<Vec<_>>(),
+            [(90, "e")]
+        );
+        assert_eq!(sparse.remove(newer), Some("e"));
+        assert!(sparse.is_empty());
+    }
+
+    #[test]
+    fn try_insert() {
+        let mut map = Slab::default();
+        let a = map.try_insert_within(0, 2).unwrap();
+        map.try_insert_within(1, 2).unwrap();
+        assert_eq!(
+            map.try_insert_within(2, 2).unwrap_err(),
+            SlabError::CapacityExhausted
+        );
+        assert_eq!(map.len(), 2);
+
+        // A free slot can still be reused at the limit.
+        map.remove(a);
+        let b = map.try_insert_within(3, 2).unwrap();
+        assert_eq!(b.index(), a.index());
+        assert!(map.try_insert(4).is_ok());
+    }
+
+    // Filling `u32::MAX` slots takes far too much memory for a unit test, so
+    // the slab's limit is covered by `try_insert` above and the conversion
+    // it relies on is checked here.
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn index_conversion_overflow() {
+        let past = u32::MAX as usize + 1;
+        assert_eq!(u32::try_from_usize(past - 1), Some(u32::MAX));
+        assert_eq!(u32::try_from_usize(past), None);
+        assert_eq!(u64::try_from_usize(past), Some(past as u64));
+        if cfg!(debug_assertions) {
+            assert!(std::panic::catch_unwind(|| u32::from_usize(past)).is_err());
+        }
+    }
+
+    #[test]
+    fn get_unchecked() {
+        let mut map = Slab::default();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        unsafe {
+            *map.get_unchecked_mut(b) += 1;
+            assert_eq!(*map.get_unchecked(a), 1);
+            assert_eq!(*map.get_unchecked(b), 3);
+        }
+    }
+
+    #[test]
+    fn compact() {
+        let mut map = Slab::default();
+        let keys: Vec<_> = (0..8).map(|i| map.insert(i)).collect();
+        for &i in &[0, 2, 3, 6] {
+            map.remove(keys[i]);
+        }
+        let remap = map.compact();
+        assert_eq!(remap.len(), 4);
+        assert!(map.validate().is_ok());
+
+        let live: Vec<_> = map.iter().map(|(k, v)| (k.index(), *v)).collect();
+        assert_eq!(live, [(0, 1), (1, 4), (2, 5), (3, 7)]);
+        for &i in &[1, 4, 5, 7] {
+            let new = *remap.get(keys[i]).unwrap();
+            assert_eq!(map.get(new), Some(&(i as i32)));
+            if new.index() != keys[i].index() {
+                assert_eq!(map.get(keys[i]), None);
+            }
+        }
+        // Every value moved, so no old key aliases a moved value.
+        assert!(keys.iter().all(|&k| map.get(k).is_none()));
+
+        let k = map.insert(8);
+        assert_eq!(k.index(), 4);
+        map.shrink_to_fit();
+        assert_eq!(map.values.len(), 5);
+    }
+
+    #[test]
+    fn retain_with_holes() {
+        let mut map = Slab::default();
+        let mut data = AssociatedData::new();
+        let keys: Vec<_> = (0..6).map(|i| map.insert(i)).collect();
+        keys.iter().for_each(|&k| {
+            data.insert(k, ());
+        });
+        for &k in &keys[1..3] {
+            map.remove(k);
+            data.remove(k);
+        }
+        assert_eq!(map.retain(|_, _| true), 0);
+        assert_eq!(data.retain(|_, _| true), 0);
+        assert_eq!((map.len(), data.len()), (4, 4));
+
+        assert_eq!(map.retain(|_, v| *v % 2 == 0), 2);
+        assert_eq!(data.retain(|k, _| k.index() % 2 == 0), 2);
+        assert_eq!((map.len(), data.len()), (2, 2));
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn associated_values() {
+        let mut map = Slab::default();
+        let mut data = AssociatedData::new();
+        let keys: Vec<_> = (0..3).map(|i| map.insert(i)).collect();
+        keys.iter().for_each(|&k| {
+            data.insert(k, k.index());
+        });
+        data.remove(keys[0]);
+        assert_eq!(data.values().count(), data.len());
+        assert_eq!(data.values_mut().count(), data.len());
+
+        // Clearing must leave already-vacant slots vacant.
+        data.clear();
+        assert_eq!(data.values().count(), 0);
+        assert_eq!(data.iter().count(), 0);
+    }
+
+    new_key_type! {
+        struct EntityKey;
+    }
+
+    #[test]
+    fn new_key_type() {
+        use std::collections::HashSet;
+
+        let mut map: Slab<EntityKey<i32>, i32> = Slab::new();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        assert_eq!(map.get(a), Some(&1));
+        assert!(a < b);
+        assert_eq!(a, a.clone());
+        assert_eq!(HashSet::from([a, b, a]).len(), 2);
+        map.remove(a);
+        assert_ne!(map.insert(3), a);
+    }
+
+    #[test]
+    fn keys_as_map_keys() {
+        use std::collections::{BTreeMap, HashMap};
+
+        let mut map = Slab::default();
+        let a = map.insert("a");
+        let b = map.insert("b");
+        let names: HashMap<_, _> = [(a, 1), (b, 2)].into_iter().collect();
+        assert_eq!(names[&a], 1);
+
+        map.remove(a);
+        let c = map.insert("c");
+        assert_eq!(c.index(), a.index());
+        assert!(!names.contains_key(&c));
+
+        let ordered: BTreeMap<_, _> = [(c, 3), (b, 2), (a, 1)].into_iter().collect();
+        assert_eq!(ordered.keys().copied().collect::<Vec<_>>(), [a, c, b]);
     }
 
     #[test]
-    fn iter() {
+    fn swap() {
         let mut map = Slab::default();
-        (0..10).for_each(|i| {
-            map.insert(i);
+        let a = map.insert("a");
+        let b = map.insert("b");
+        assert!(map.swap(a, b));
+        assert_eq!((map[a], map[b]), ("b", "a"));
+        assert!(map.swap(a, a));
+        assert_eq!(map[a], "b");
+
+        map.remove(b);
+        assert!(!map.swap(a, b));
+        assert_eq!(map[a], "b");
+    }
+
+    #[test]
+    fn remove_many_keys() {
+        let mut map = Slab::default();
+        let keys: Vec<_> = (0..6).map(|i| map.insert(i)).collect();
+        map.remove(keys[5]);
+        let batch = [keys[0], keys[2], keys[0], keys[5], keys[2], keys[3]];
+        assert_eq!(map.remove_many(batch), 3);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.free.len(), 4);
+        assert_eq!(map.validate(), Ok(()));
+        assert_eq!(map.values().copied().collect::<Vec<_>>(), [1, 4]);
+    }
+
+    #[test]
+    fn iter_at() {
+        let mut map = Slab::default();
+        let keys: Vec<_> = (0..5).map(|i| map.insert(i)).collect();
+        let at = |map: &Slab<_, _>, k| map.iter_at(k).map(|(_, v)| *v).collect::<Vec<_>>();
+        assert_eq!(at(&map, keys[2]), [2, 3, 4]);
+
+        map.remove(keys[2]);
+        assert_eq!(at(&map, keys[2]), [3, 4]);
+        map.remove(keys[4]);
+        map.shrink_to_fit();
+        assert!(at(&map, keys[4]).is_empty());
+    }
+
+    #[test]
+    fn associated_reserve() {
+        let mut map = Slab::default();
+        let mut data = AssociatedData::new();
+        let k = map.insert(0);
+        data.insert(k, "a");
+        data.reserve(99);
+        assert_eq!(data.items.len(), 100);
+        assert_eq!(data.len(), 1);
+        assert_eq!(data.get(k), Some(&"a"));
+
+        let capacity = data.items.capacity();
+        let keys: Vec<_> = (1..100).map(|i| map.insert(i)).collect();
+        keys.iter().for_each(|&k| {
+            data.insert(k, "b");
         });
-        let mut iter = map.iter();
-        assert_eq!(iter.next().map(|v| v.1), Some(&0), "{map:?}");
+        assert_eq!(data.items.capacity(), capacity);
+        data.reserve(10);
+        assert_eq!((data.items.len(), data.len()), (100, 100));
     }
 
     #[test]
-    fn clear() {
+    fn debug_summary() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::with_capacity(4);
+        let k = map.insert(1);
+        map.insert(2);
+        map.remove(k);
+        assert_eq!(format!("{map:?}"), "Slab { len: 1, capacity: 4, free: 1 }");
+        let full = format!("{map:#?}");
+        assert!(full.contains("1v2: 2"));
+        assert!(!full.contains("0v"));
+    }
+
+    #[test]
+    fn first_and_last_key() {
         let mut map = Slab::default();
-        (0..10).for_each(|i| {
-            map.insert(i);
+        assert!(map.first_key().is_none() && map.last_key().is_none());
+        let keys: Vec<_> = (0..5).map(|i| map.insert(i)).collect();
+        map.remove(keys[0]);
+        map.remove(keys[4]);
+        assert_eq!(map.first_key(), Some(keys[1]));
+        assert_eq!(map.last_key(), Some(keys[3]));
+        map.clear();
+        assert!(map.first_key().is_none() && map.last_key().is_none());
+    }
+
+    #[test]
+    fn remove_versioned() {
+        let mut map = Slab::default();
+        let k = map.insert("a");
+        let (value, version) = map.remove_versioned(k).unwrap();
+        assert_eq!((value, version.get()), ("a", k.version().get() + 1));
+        assert!(map.remove_versioned(k).is_none());
+        let k = map.insert("b");
+        assert_eq!(k.version().get(), version.get() + 1);
+    }
+
+    #[test]
+    fn into_iter_with_keys() {
+        let mut map = Slab::default();
+        let keys: Vec<_> = (0..4).map(|i| map.insert(i)).collect();
+        map.remove(keys[1]);
+        let stale = keys[1];
+
+        let entries: Vec<_> = map.into_iter_with_keys().collect();
+        assert_eq!(entries, [(keys[0], 0), (keys[2], 2), (keys[3], 3)]);
+
+        let map = Slab::from_keyed(entries).unwrap();
+        assert_eq!(map.len(), 3);
+        assert!(keys
+            .iter()
+            .all(|&k| k == stale || map[k] == k.index() as i32));
+        assert_eq!(map.get(stale), None);
+    }
+
+    #[test]
+    fn associated_bound() {
+        let mut map = Slab::default();
+        let keys: Vec<_> = (0..10).map(|i| map.insert(i)).collect();
+        let mut data = AssociatedData::with_bound(4);
+        assert_eq!(data.insert(keys[4], "in"), None);
+        assert_eq!(data.insert(keys[9], "out"), None);
+        assert_eq!(data.get(keys[9]), None);
+        assert!(matches!(data.entry(keys[9]), Entry::Rejected(_)));
+        data.reserve(1000);
+        assert_eq!(data.items.len(), 5);
+        assert_eq!(data.len(), 1);
+        assert_eq!(data.get(keys[4]), Some(&"in"));
+    }
+
+    #[test]
+    fn for_each_mut() {
+        let mut map = Slab::default();
+        let keys: Vec<_> = (0..6).map(|i| map.insert(i)).collect();
+        let mut found = None;
+        map.for_each_mut(|k, v| {
+            *v *= 10;
+            if *v == 30 {
+                found = Some(k);
+                return ControlFlow::Break(());
+            }
+            ControlFlow::Continue(())
         });
-        assert_eq!(map.capacity(), 16, "{map:?}");
+        assert_eq!(found, Some(keys[3]));
+        assert_eq!(
+            map.values().copied().collect::<Vec<_>>(),
+            [0, 10, 20, 30, 4, 5]
+        );
+        assert_eq!(map.remove(found.unwrap()), Some(30));
+    }
+
+    #[test]
+    fn associated_clone() {
+        struct NotClone;
+
+        let mut map: Slab<DefaultKey<NotClone>, NotClone> = Slab::new();
+        let mut data = AssociatedData::new();
+        let a = map.insert(NotClone);
+        let b = map.insert(NotClone);
+        data.insert(a, 1);
+        data.insert(b, 2);
+        data.remove(a);
+
+        let copy = data.clone();
+        assert_eq!(copy.len(), data.len());
+        assert_eq!(copy.get(a), None);
+        assert_eq!(copy.get(b), data.get(b));
+        assert_eq!(copy.items[0].version, data.items[0].version);
+    }
+
+    #[test]
+    fn wide_key() {
+        let mut map: WideSlab<&str> = Slab::new();
+        let a = map.insert("a");
+        let b = map.insert("b");
+        assert_eq!(b.index(), 1u64);
+        assert_eq!(map.get(a), Some(&"a"));
+
+        assert_eq!(map.remove(a), Some("a"));
+        assert_eq!(map.get(a), None);
+        let c = map.insert("c");
+        assert_eq!(c.index(), a.index());
+        assert_eq!(c.version().get(), 4u64);
+        assert_eq!(map.get(a), None);
+        assert_eq!(map.len(), 2);
+        map.validate().unwrap();
+    }
+
+    #[test]
+    fn get2_mut() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        let (x, y) = map.get2_mut(a, b).unwrap();
+        core::mem::swap(x, y);
+        assert_eq!((map[a], map[b]), (2, 1));
+
+        assert!(map.get2_mut(a, a).is_none());
+        map.remove(b);
+        let c = map.insert(3);
+        assert!(map.get2_mut(a, b).is_none());
+        assert!(map.get2_mut(b, a).is_none());
+        assert!(map.get2_mut(a, c).is_some());
+    }
+
+    #[test]
+    fn key_at_index() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        assert_eq!(map.key_at_index(0), Some(a));
+        assert_eq!(map.key_at_index(2), None);
+
+        map.remove(a);
+        assert_eq!(map.key_at_index(0), None);
+        let c = map.insert(3);
+        assert_eq!(map.key_at_index(0), Some(c));
+        assert_ne!(map.key_at_index(0), Some(a));
+        assert_eq!(map.key_at_index(1), Some(b));
+    }
+
+    #[test]
+    fn validate_generation() {
+        #[derive(Clone, Copy, PartialEq, Debug)]
+        struct GenKey {
+            data: KeyData<i32>,
+            generation: u64,
+        }
+
+        impl Key<i32> for GenKey {
+            fn data(&self) -> &KeyData<i32> {
+                &self.data
+            }
+
+            fn init(version: NonZeroU32, index: u32) -> Self {
+                Self::init_with_generation(version, index, 0)
+            }
+
+            fn init_with_generation(version: NonZeroU32, index: u32, generation: u64) -> Self {
+                Self {
+                    data: KeyData::new(version, index),
+                    generation,
+                }
+            }
+
+            fn validate_generation(&self, slab_generation: u64) -> bool {
+                self.generation == slab_generation
+            }
+        }
+
+        let mut map: Slab<GenKey, i32> = Slab::new();
+        let a = map.insert(1);
+        assert_eq!(map.get(a), Some(&1));
         map.clear();
-        assert_eq!(map.len(), 0, "{map:?}");
-        assert_eq!(map.capacity(), 16, "{map:?}");
-        (0..10).for_each(|i| {
-            map.insert(i);
+        assert_eq!(map.generation(), 1);
+
+        let b = map.insert(2);
+        assert_eq!(b.generation, 1);
+        let forged = GenKey { generation: 0, ..b };
+        assert_eq!(map.get(b), Some(&2));
+        assert_eq!(map.get(forged), None);
+        assert_eq!(map.remove(forged), None);
+        assert_eq!(map.len(), 1);
+
+        let c = map.insert(3);
+        for key in [
+            map.iter().next().unwrap().0,
+            map.keys().next_back().unwrap(),
+        ] {
+            assert!(map.contains_key(key));
+        }
+        assert_eq!(map.get_key_value(c), Some((c, &3)));
+        assert_eq!(map.get(map.key_at_index(c.index()).unwrap()), Some(&3));
+        for (key, value) in map.iter_mut() {
+            *value += 10;
+            assert_eq!(key.generation, 1);
+        }
+        assert_eq!(map.get(c), Some(&13));
+
+        let copy: Slab<GenKey, i32> = unsafe { Slab::from_raw_parts(map.clone().into_raw_parts()) };
+        assert_eq!(copy.get(b), Some(&12));
+    }
+
+    #[test]
+    fn insert_or_update() {
+        let mut map: Slab<DefaultKey<()>, ()> = Slab::new();
+        let a = map.insert(());
+        let mut damage = AssociatedData::<_, u32, _>::new();
+        for hit in [3, 4, 5] {
+            damage.insert_or_update(a, hit, |total| *total += hit);
+        }
+        assert_eq!(damage.get(a), Some(&12));
+        assert_eq!(damage.len(), 1);
+
+        map.remove(a);
+        let b = map.insert(());
+        damage.insert_or_update(b, 1, |total| *total += 1);
+        assert_eq!(damage.get(b), Some(&1));
+        damage.insert_or_update(a, 100, |total| *total += 100);
+        assert_eq!(damage.get(b), Some(&1));
+        assert_eq!(damage.get(a), None);
+        assert_eq!(damage.len(), 1);
+    }
+
+    #[test]
+    fn stats_slots() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        assert_eq!(map.stats().highest_occupied, None);
+        let keys: Vec<_> = (0..4).map(|i| map.insert(i)).collect();
+        map.remove(keys[1]);
+        map.remove(keys[3]);
+        let stats = map.stats();
+        assert_eq!(stats.len, 2);
+        assert_eq!(stats.vacant, 2);
+        assert_eq!(stats.free_len, 2);
+        assert_eq!(stats.highest_occupied, Some(2));
+
+        map.clear();
+        let stats = map.stats();
+        assert_eq!(stats.vacant, 4);
+        assert_eq!(stats.free_len, stats.vacant - map.retired_slots());
+    }
+
+    #[test]
+    fn split_at_key() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        let c = map.insert(3);
+        map.remove(c);
+
+        let (value, view) = map.split_at_key(b).unwrap();
+        assert_eq!(view.get(b), None);
+        assert_eq!(view.get(c), None);
+        assert_eq!(view.get(a), Some(&1));
+        *value += *view.get(a).unwrap();
+        assert_eq!(map[b], 3);
+
+        let (_, view) = map.split_at_key(a).unwrap();
+        assert!(!view.contains_key(a));
+        assert_eq!(view.get(b), Some(&3));
+        assert!(map.split_at_key(c).is_none());
+    }
+
+    #[test]
+    fn truncate_vacant_tail() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::with_capacity(8);
+        let keys: Vec<_> = (0..8).map(|i| map.insert(i)).collect();
+        map.remove(keys[2]);
+        for &k in &keys[5..] {
+            map.remove(k);
+        }
+        let capacity = map.capacity();
+        map.truncate_vacant_tail();
+        assert_eq!(map.values.len(), 5);
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(map.free, [2]);
+        assert!(keys[..5]
+            .iter()
+            .all(|&k| map.contains_key(k) != (k == keys[2])));
+
+        let k = map.insert(9);
+        let fresh = map.insert(10);
+        assert_eq!(k.index(), 2);
+        assert_eq!(fresh.index(), 5);
+        assert_eq!(map.get(keys[5]), None);
+        map.validate().unwrap();
+
+        let keys: Vec<_> = (0..3).map(|i| map.insert(i)).collect();
+        for &k in keys.iter().rev() {
+            map.remove(k);
+        }
+        map.remove(fresh);
+        map.truncate_vacant_tail();
+        assert_eq!(map.values.len(), 5);
+        assert!(map.free.is_empty());
+        map.validate().unwrap();
+
+        let keys: Vec<_> = (0..3).map(|i| map.insert(i)).collect();
+        map.remove(keys[2]);
+        map.remove(k);
+        map.truncate_vacant_tail();
+        assert_eq!(map.values.len(), 7);
+        assert_eq!(map.free, [2]);
+        map.validate().unwrap();
+    }
+
+    #[test]
+    fn into_iter_refs() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..3).map(|i| map.insert(i)).collect();
+        map.remove(keys[1]);
+        for (_, v) in &mut map {
+            *v *= 10;
+        }
+        let pairs: Vec<_> = (&map).into_iter().map(|(k, &v)| (k, v)).collect();
+        assert_eq!(pairs, [(keys[0], 0), (keys[2], 20)]);
+
+        let mut data = AssociatedData::new();
+        for &k in &keys {
+            data.insert(k, k.index());
+        }
+        data.remove(keys[0]);
+        for (k, v) in &mut data {
+            *v += k.index();
+        }
+        let iter: AssociatedIter<'_, _, _, _> = (&data).into_iter();
+        assert_eq!(iter.len(), 2);
+        let values: Vec<_> = iter.map(|(_, &v)| v).collect();
+        assert_eq!(values, [2, 4]);
+    }
+
+    #[test]
+    fn join() {
+        let mut map: Slab<DefaultKey<&str>, &str> = Slab::new();
+        let a = map.insert("a");
+        let b = map.insert("b");
+        let c = map.insert("c");
+        let mut data = AssociatedData::new();
+        data.insert(a, 1);
+        data.insert(c, 3);
+        map.remove(c);
+        let d = map.insert("d");
+        data.insert(b, 2);
+        map.remove(b);
+
+        let joined: Vec<_> = data.join(&map).collect();
+        assert_eq!(joined, [(a, &1, &"a")]);
+        data.insert(d, 4);
+        assert_eq!(data.join(&map).count(), 2);
+    }
+
+    #[test]
+    fn replace() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let a = map.insert(1);
+        assert_eq!(map.replace(a, 2), Some(1));
+        assert_eq!(map.get(a), Some(&2));
+
+        map.remove(a);
+        assert_eq!(map.replace(a, 3), None);
+        assert_eq!(map.replace(DefaultKey::init(a.version(), 5), 3), None);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn associated_data_default() {
+        struct NotDefault;
+
+        #[derive(Default)]
+        struct State {
+            slab: Slab<DefaultKey<NotDefault>, NotDefault>,
+            names: AssociatedData<DefaultKey<NotDefault>, &'static str, NotDefault>,
+        }
+
+        let mut state = State::default();
+        let a = state.slab.insert(NotDefault);
+        state.names.insert(a, "a");
+        assert_eq!(state.names.get(a), Some(&"a"));
+    }
+
+    #[test]
+    fn associated_data_with_capacity() {
+        let mut map: Slab<DefaultKey<()>, ()> = Slab::with_capacity(16);
+        let keys: Vec<_> = (0..16).map(|_| map.insert(())).collect();
+        let mut data = AssociatedData::with_capacity(map.capacity());
+        let ptr = data.items.as_ptr();
+        for &k in keys.iter().rev() {
+            data.insert(k, k.index());
+        }
+        assert_eq!(data.items.as_ptr(), ptr);
+        assert_eq!(data.items.capacity(), 16);
+        assert_eq!(data.len(), 16);
+    }
+
+    #[test]
+    fn iter_order() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = [30, 10, 20].into_iter().map(|v| map.insert(v)).collect();
+        map.remove(keys[1]);
+        let d = map.insert(5);
+        assert_eq!(d.index(), 1);
+        let values: Vec<_> = map.values().copied().collect();
+        assert_eq!(values, [30, 5, 20]);
+
+        let sorted: Vec<_> = map.iter_sorted_by(|a, b| a.cmp(b));
+        assert_eq!(sorted, [(d, &5), (keys[2], &20), (keys[0], &30)]);
+    }
+
+    #[test]
+    fn cursor_mut() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..6).map(|i| map.insert(i)).collect();
+        map.remove(keys[0]);
+
+        let mut cursor = map.cursor_mut();
+        let mut remove = true;
+        while let Some((_, value)) = cursor.current() {
+            *value *= 10;
+            if remove {
+                assert!(cursor.remove_current().is_some());
+                assert!(cursor.current().is_none());
+                assert!(cursor.remove_current().is_none());
+            }
+            remove = !remove;
+            cursor.advance();
+        }
+        cursor.advance();
+        assert!(cursor.current().is_none());
+
+        let values: Vec<_> = map.values().copied().collect();
+        assert_eq!(values, [20, 40]);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(keys[1]), None);
+        assert_eq!(map.insert(7).index(), keys[5].index());
+        map.validate().unwrap();
+    }
+
+    #[test]
+    fn any_typed_key() {
+        let mut map = AnySlab::new();
+        let n: TypedKey<u32> = map.insert(5u32);
+        let s: TypedKey<&str> = map.insert("five");
+        assert_eq!(map.get(&n), Some(&5));
+        assert_eq!(map.get(&s), Some(&"five"));
+        assert_eq!(map.remove(&s), Some("five"));
+        assert_eq!(map.get(&s), None);
+
+        let forged = TypedKey::<String>::init(n.version(), n.index());
+        assert_eq!(map.get(&forged), None);
+        assert_eq!(map.remove(&forged), None);
+        assert_eq!(
+            map.slab.get(AnySlab::erase(&n)).unwrap().type_id,
+            TypeId::of::<u32>()
+        );
+    }
+
+    #[test]
+    fn defragment_free_list() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..6).map(|i| map.insert(i)).collect();
+        for i in [1, 4, 0, 3] {
+            map.remove(keys[i]);
+        }
+        assert_eq!(map.free_list_len(), 4);
+        map.defragment_free_list();
+        let indices: Vec<_> = (0..4).map(|i| map.insert(i).index()).collect();
+        assert_eq!(indices, [0, 1, 3, 4]);
+        assert_eq!(map.free_list_len(), 0);
+    }
+
+    #[test]
+    fn access_key_as_slab_key() {
+        let mut map: Slab<AccessKey<DefaultKey<i32>, i32>, i32> = Slab::new();
+        let a = map.insert(1);
+        let b = map.insert(2);
+        map.remove(a.clone());
+        let keys: Vec<_> = map.keys().map(|k| *k.key()).collect();
+        assert_eq!(keys, [*b.key()]);
+        assert_eq!(map.get(b), Some(&2));
+        map.retain(|_, v| *v > 5);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn insert_at() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..5).map(|i| map.insert(i)).collect();
+        map.remove(keys[1]);
+        map.remove(keys[3]);
+        let reused = map.insert(10);
+
+        let mut copy: Slab<DefaultKey<i32>, i32> = Slab::new();
+        for (key, &value) in map.iter().rev() {
+            assert_eq!(copy.insert_at(key.index(), key.version(), value), Ok(key));
+        }
+        assert_eq!(copy, map);
+        assert_eq!(copy.get(reused), Some(&10));
+        assert_eq!(copy.get(keys[1]), None);
+        copy.validate().unwrap();
+
+        let odd = NonZeroU32::new(3).unwrap();
+        let next = NonZeroU32::new(6).unwrap();
+        assert_eq!(
+            copy.insert_at(0, odd, 0),
+            Err(InsertAtError::InvalidVersion(0))
+        );
+        assert_eq!(copy.insert_at(0, next, 0), Err(InsertAtError::Occupied(0)));
+        assert_eq!(copy.insert_at(0, keys[0].version(), 7), Ok(keys[0]));
+        assert_eq!(copy[keys[0]], 7);
+        assert_eq!(copy.len(), 4);
+        assert_eq!(copy.insert(11).index(), keys[1].index());
+    }
+
+    #[test]
+    fn raw_parts() {
+        let mut map: Slab<DefaultKey<String>, String> = Slab::new();
+        let keys: Vec<_> = (0..4).map(|i| map.insert(i.to_string())).collect();
+        map.remove(keys[2]);
+        let parts = map.into_raw_parts();
+        assert_eq!((parts.free.as_slice(), parts.taken), ([2].as_slice(), 3));
+
+        let map: Slab<DefaultKey<String>, String> = unsafe { Slab::from_raw_parts(parts) };
+        assert_eq!(map.get(keys[3]).map(String::as_str), Some("3"));
+        assert_eq!(map.get(keys[2]), None);
+        assert_eq!(map.len(), 3);
+
+        let values = map.into_raw_parts().values;
+        let (version, value) = values.into_iter().next().unwrap().into_parts();
+        assert_eq!(version, keys[0].version());
+        assert!(Slot::<_, u32>::from_parts(version, None::<String>).is_none());
+        assert!(Slot::<_, u32>::from_parts(version, value).is_some());
+    }
+
+    #[test]
+    fn associated_data_shrink_to_fit() {
+        let mut data = AssociatedData::<DefaultKey<()>, i32, ()>::new();
+        let low = DefaultKey::init(NonZeroU32::new(2).unwrap(), 3);
+        let high = DefaultKey::init(NonZeroU32::new(2).unwrap(), 10_000);
+        data.insert(low, 1);
+        data.insert(high, 2);
+        data.remove(high);
+        assert_eq!(data.items.len(), 10_001);
+
+        data.shrink_to_fit();
+        assert_eq!(data.items.len(), 4);
+        assert!(data.items.capacity() < 10_001);
+        assert_eq!(data.get(low), Some(&1));
+        assert_eq!(data.insert(high, 3), None);
+        assert_eq!(data.get(high), Some(&3));
+    }
+
+    #[test]
+    fn slot_parity() {
+        let mut slot = Slot::<i32>::new();
+        assert!(slot.is_vacant() && !slot.is_occupied());
+        slot.update(1);
+        assert!(slot.is_occupied() && !slot.is_vacant());
+        slot.debug_assert_consistent();
+        slot.vacate();
+        assert!(slot.is_vacant());
+        slot.debug_assert_consistent();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "does not match its value parity")]
+    fn slot_parity_corrupted() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let a = map.insert(1);
+        map.values[0].value = None;
+        map.remove(a);
+    }
+
+    #[test]
+    fn map_values() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..4).map(|i| map.insert(i)).collect();
+        map.remove(keys[1]);
+        let mut strings: Slab<DefaultKey<String>, String> = map.map_values(|v| v.to_string());
+        let same = |k: DefaultKey<i32>| DefaultKey::<String>::init(k.version(), k.index());
+
+        assert_eq!(strings.get(same(keys[2])).map(String::as_str), Some("2"));
+        assert_eq!(strings.get(same(keys[1])), None);
+        assert_eq!(strings.len(), 3);
+        let reused = strings.insert(String::new());
+        assert_eq!(reused.index(), keys[1].index());
+        assert_ne!(reused, same(keys[1]));
+        strings.validate().unwrap();
+    }
+
+    #[test]
+    fn associated_data_extend_reserves_once() {
+        let version = NonZeroU32::new(2).unwrap();
+        let mut pairs: Vec<_> = (0..10_000)
+            .map(|i| (DefaultKey::<()>::init(version, i), i))
+            .collect();
+        pairs.insert(5_000, (DefaultKey::init(version, 100_000), 100_000));
+
+        let mut data = AssociatedData::new();
+        data.extend(pairs);
+        assert_eq!(data.items.capacity(), 100_001);
+        assert_eq!(data.len(), 10_001);
+        assert_eq!(data.get(DefaultKey::init(version, 100_000)), Some(&100_000));
+    }
+
+    #[test]
+    fn get_key_value() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let a = map.insert(1);
+        assert_eq!(map.get_key_value(a), Some((a, &1)));
+        map.remove(a);
+        let b = map.insert(2);
+        assert_eq!(map.get_key_value(a), None);
+        assert_eq!(map.get_key_value(b), Some((b, &2)));
+    }
+
+    #[test]
+    fn frozen_slab_threads() {
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<DefaultKey<std::rc::Rc<i32>>>();
+        send_sync::<KeyData<core::cell::Cell<i32>>>();
+        send_sync::<Slab<DefaultKey<i32>, i32>>();
+        send_sync::<AssociatedData<DefaultKey<std::rc::Rc<i32>>, i32, std::rc::Rc<i32>>>();
+        send_sync::<FrozenSlab<DefaultKey<String>, String>>();
+
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..8).map(|i| map.insert(i)).collect();
+        let frozen = map.freeze();
+        let sums: Vec<i32> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| keys.iter().map(|&k| frozen[k]).sum::<i32>()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
         });
-        assert_eq!(map.len(), 10, "{map:?}");
-        assert_eq!(map.capacity(), 16, "{map:?}");
+        assert_eq!(sums, [28; 4]);
+        let mut map = frozen.thaw();
+        assert_eq!(map.remove(keys[0]), Some(0));
+    }
+
+    #[test]
+    fn retain_map() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..6).map(|i| map.insert(i)).collect();
+        map.remove(keys[0]);
+        let removed = map.retain_map(|_, v| (v % 2 == 0).then_some(v * 10));
+        assert_eq!(removed, 3);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(keys[2]), Some(&20));
+        assert_eq!(map.get(keys[4]), Some(&40));
+        assert_eq!(map.get(keys[3]), None);
+        map.validate().unwrap();
+
+        let reused: Vec<_> = (0..4).map(|i| map.insert(i).index()).collect();
+        assert_eq!(reused, [5, 3, 1, 0]);
+    }
+
+    #[test]
+    fn ffi_round_trip() {
+        assert_eq!(KeyData::<()>::from_ffi(0), None);
+        assert_eq!(DefaultKey::<()>::from_ffi(u32::MAX as u64), None);
+
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        for _ in 0..10_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let index = state as u32;
+            let version = NonZeroU32::new((state >> 32) as u32 | 1).unwrap();
+            let key = DefaultKey::<()>::init(version, index);
+            let packed = key.as_ffi();
+            assert_eq!(DefaultKey::from_ffi(packed), Some(key));
+            assert_eq!(KeyData::<()>::from_ffi(packed).unwrap().as_ffi(), packed);
+        }
+
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let a = map.insert(1);
+        assert_eq!(map.get(DefaultKey::from_ffi(a.as_ffi()).unwrap()), Some(&1));
+    }
+
+    #[test]
+    fn collect_keys_into() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..8).map(|i| map.insert(i)).collect();
+        let mut buf = Vec::new();
+        map.collect_keys_into(&mut buf);
+        assert_eq!(buf, keys);
+        let capacity = buf.capacity();
+        let ptr = buf.as_ptr();
+
+        for &k in &keys[..4] {
+            map.remove(k);
+            map.collect_keys_into(&mut buf);
+            map.insert(0);
+            map.collect_keys_into(&mut buf);
+        }
+        assert_eq!(buf.len(), 8);
+        assert_eq!((buf.capacity(), buf.as_ptr()), (capacity, ptr));
+    }
+
+    #[test]
+    fn insert_with_policy() {
+        use ConflictPolicy::*;
+        let key = |version, index| DefaultKey::<()>::init(NonZeroU32::new(version).unwrap(), index);
+        let mut data = AssociatedData::with_bound(4);
+        assert_eq!(
+            data.insert_with_policy(key(4, 0), "a", RefuseAny),
+            InsertOutcome::Inserted
+        );
+        assert_eq!(
+            data.insert_with_policy(key(4, 0), "b", RefuseAny),
+            InsertOutcome::Replaced("a")
+        );
+        for policy in [RefuseNewer, RefuseAny] {
+            assert_eq!(
+                data.insert_with_policy(key(2, 0), "old", policy),
+                InsertOutcome::Rejected(Rejection::NewerVersion)
+            );
+        }
+        assert_eq!(
+            data.insert_with_policy(key(6, 0), "new", RefuseAny),
+            InsertOutcome::Rejected(Rejection::OlderVersion)
+        );
+        assert_eq!(
+            data.insert_with_policy(key(6, 0), "new", RefuseNewer),
+            InsertOutcome::Overwrote("b")
+        );
+        assert_eq!(
+            data.insert_with_policy(key(2, 0), "old", AlwaysOverwrite),
+            InsertOutcome::Overwrote("new")
+        );
+        assert_eq!(data.get(key(2, 0)), Some(&"old"));
+        assert_eq!(
+            data.insert_with_policy(key(2, 5), "far", AlwaysOverwrite),
+            InsertOutcome::Rejected(Rejection::OutOfBounds)
+        );
+        assert_eq!(data.len(), 1);
+    }
+
+    #[test]
+    fn occupied_indices() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..6).map(|i| map.insert(i)).collect();
+        map.remove(keys[0]);
+        map.remove(keys[4]);
+        let indices: Vec<_> = map.occupied_indices().collect();
+        assert_eq!(indices, [1, 2, 3, 5]);
+        assert!(indices
+            .iter()
+            .copied()
+            .eq(map.iter().map(|(k, _)| k.index())));
+    }
+
+    #[test]
+    fn clear_and_shrink() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..100).map(|i| map.insert(i)).collect();
+        let capacity = map.capacity();
+        map.clear();
+        assert_eq!((map.len(), map.capacity()), (0, capacity));
+
+        let keys2: Vec<_> = (0..100).map(|i| map.insert(i)).collect();
+        map.clear_and_shrink();
+        assert_eq!((map.len(), map.capacity()), (0, 0));
+        assert_eq!(map.free.capacity(), 0);
+
+        let fresh = map.insert(1);
+        assert_eq!(fresh.index(), 0);
+        assert_eq!(map.get(keys[0]), None);
+        assert_eq!(map.get(keys2[0]), None);
+        map.validate().unwrap();
+    }
+
+    #[test]
+    fn retain_versioned() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..3).map(|i| map.insert(i)).collect();
+        let mut bumped = vec![];
+        let removed = map.retain_versioned(|k, v| match *v {
+            0 => Retain::Keep,
+            1 => Retain::Remove,
+            _ => {
+                bumped.push(k);
+                *v = 20;
+                Retain::KeepBumped
+            }
+        });
+        assert_eq!(removed, 1);
+        assert_eq!(bumped, [keys[2]]);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(keys[0]), Some(&0));
+        assert_eq!(map.get(keys[1]), None);
+        assert_eq!(map.get(keys[2]), None);
+        let current = map.key_at_index(2).unwrap();
+        assert_eq!(current.version().get(), keys[2].version().get() + 2);
+        assert_eq!(map.get(current), Some(&20));
+        assert_eq!(map.free, [1]);
+        map.validate().unwrap();
+
+        map.values[2].version = NonZeroU32::new(u32::MAX - 1).unwrap();
+        assert_eq!(map.retain_versioned(|_, _| Retain::KeepBumped), 1);
+        assert!(map.values[2].retired());
+        assert_eq!(map.len(), 1);
+        map.validate().unwrap();
+    }
+
+    #[test]
+    fn insert_at_after_shrink() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..10).map(|i| map.insert(i)).collect();
+        for &key in &keys[5..] {
+            map.remove(key);
+        }
+        map.shrink_to_fit();
+        let stale = NonZeroU32::new(2).unwrap();
+        assert_eq!(
+            map.insert_at(9, stale, 9),
+            Err(InsertAtError::InvalidVersion(9))
+        );
+        let at = map.insert_at(9, map.fresh_version, 9).unwrap();
+        let key = map.insert(5);
+        assert_eq!(key.index(), 5);
+        assert_ne!(key, keys[5]);
+        assert_eq!(map.get(keys[5]), None);
+        assert_eq!(map.get(keys[9]), None);
+        assert_eq!(map[at], 9);
+        map.validate().unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_after_shrink() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..5).map(|i| map.insert(i)).collect();
+        map.remove(keys[4]);
+        map.remove(keys[3]);
+        map.shrink_to_fit();
+        let json = serde_json::to_string(&map).unwrap();
+        let mut loaded: Slab<DefaultKey<i32>, i32> = serde_json::from_str(&json).unwrap();
+        let key = loaded.insert(3);
+        assert_eq!(key, map.insert(3));
+        assert_eq!(loaded.get(keys[3]), None);
+
+        let old = r#"{"slots":[{"version":2,"value":1}],"free":[]}"#;
+        let loaded: Slab<DefaultKey<i32>, i32> = serde_json::from_str(old).unwrap();
+        assert_eq!(loaded.len(), 1);
+        let odd = r#"{"slots":[],"free":[],"fresh_version":3}"#;
+        assert!(serde_json::from_str::<Slab<DefaultKey<i32>, i32>>(odd).is_err());
+
+        map.clear();
+        let json = serde_json::to_string(&map).unwrap();
+        let loaded: Slab<DefaultKey<i32>, i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.generation(), 1);
+    }
+
+    #[test]
+    fn raw_parts_after_shrink() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..4).map(|i| map.insert(i)).collect();
+        map.remove(keys[3]);
+        map.shrink_to_fit();
+        let parts = map.into_raw_parts();
+        let mut map: Slab<DefaultKey<i32>, i32> = unsafe { Slab::from_raw_parts(parts) };
+        let key = map.insert(3);
+        assert_eq!(key.index(), 3);
+        assert_eq!(map.get(keys[3]), None);
+    }
+
+    #[test]
+    fn clear_and_shrink_retired() {
+        let mut map: Slab<DefaultKey<i32>, i32> = Slab::new();
+        let keys: Vec<_> = (0..4).map(|i| map.insert(i)).collect();
+        map.values[1].version = NonZeroU32::new(u32::MAX - 1).unwrap();
+        let last = map.key_at_index(1).unwrap();
+        map.remove(last);
+        assert!(map.values[1].retired());
+
+        map.clear_and_shrink();
+        assert_eq!(map.len(), 0);
+        assert_eq!(map.values.len(), 2);
+        assert!(map.capacity() >= 2);
+        assert_eq!(map.free, [0]);
+        map.validate().unwrap();
+
+        assert_eq!(map.insert(0).index(), 0);
+        let pushed = map.insert(2);
+        assert_eq!(pushed.index(), 2);
+        assert_eq!(map.get(keys[2]), None);
+        assert_eq!(map.get(last), None);
     }
 
     #[test]
     fn access_key() {
         let mut map = Slab::default();
-        let _k = map.insert_with_access(5);
+        let k = map.insert_with_access(5);
+        assert_eq!(k.get(&map), Some(&5));
+        let moved = Box::new(map);
+        assert_eq!(k.get(&moved), Some(&5));
+        let mut map = *moved;
+        map.remove(*k.key());
+        assert_eq!(k.get(&map), None);
     }
 
     #[test]
@@ -797,7 +5602,7 @@ mod tests {
         for k in keys {
             assert_eq!(map.remove(k), Some(k.index()));
         }
-        for i in 999..=0 {
+        for i in (0..=999).rev() {
             let k = map.insert(i);
             assert_eq!(k.index(), i);
         }